@@ -0,0 +1,162 @@
+//! Bearer token authentication shared by the multimint servers
+
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use axum::{
+    extract::{Query, Request, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode, Uri},
+    middleware::Next,
+    response::Response,
+};
+use serde::Deserialize;
+use subtle::ConstantTimeEq;
+
+use crate::error::AppError;
+
+/// The token of the `Authorization: Bearer <token>` header, rejecting requests without one
+pub fn bearer_token(headers: &HeaderMap) -> Result<&str, AppError> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::UNAUTHORIZED,
+                anyhow!("Missing bearer token in Authorization header"),
+            )
+            .with_code("missing_token")
+        })
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenQuery {
+    access_token: Option<String>,
+}
+
+/// The token of the `access_token` query parameter, for clients that can't set headers, like browsers opening a WebSocket
+pub fn query_token(uri: &Uri) -> Option<String> {
+    Query::<TokenQuery>::try_from_uri(uri).ok()?.0.access_token
+}
+
+/// Compare a token with the password in constant time
+pub fn is_password(token: &str, password: &str) -> bool {
+    token.as_bytes().ct_eq(password.as_bytes()).into()
+}
+
+/// The error answered for tokens that don't authenticate anyone
+pub fn invalid_token() -> AppError {
+    AppError::new(StatusCode::UNAUTHORIZED, anyhow!("Invalid bearer token"))
+        .with_code("invalid_token")
+}
+
+/// State for the admin middleware: the server password
+#[derive(Debug, Clone)]
+pub struct AdminAuth {
+    password: Arc<str>,
+}
+
+impl AdminAuth {
+    pub fn new(password: impl Into<Arc<str>>) -> Self {
+        Self {
+            password: password.into(),
+        }
+    }
+}
+
+/// Reject requests that don't carry an `Authorization: Bearer <password>` header
+pub async fn require_admin(
+    State(auth): State<AdminAuth>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let token = bearer_token(request.headers())?;
+    if !is_password(token, &auth.password) {
+        return Err(invalid_token());
+    }
+
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const PASSWORD: &str = "correct horse battery staple";
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, authorization.parse().unwrap());
+        headers
+    }
+
+    async fn status_with_header(header: Option<&str>) -> StatusCode {
+        let app = Router::new()
+            .route("/admin", get(|| async { "ok" }))
+            .route_layer(middleware::from_fn_with_state(
+                AdminAuth::new(PASSWORD),
+                require_admin,
+            ));
+
+        let mut request = Request::get("/admin");
+        if let Some(header) = header {
+            request = request.header(AUTHORIZATION, header);
+        }
+
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[test]
+    fn bearer_token_is_extracted() {
+        assert_eq!(bearer_token(&headers("Bearer secret")).unwrap(), "secret");
+    }
+
+    #[test]
+    fn missing_or_other_scheme_has_no_token() {
+        let missing = bearer_token(&HeaderMap::new()).unwrap_err();
+        assert_eq!(missing.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(missing.code(), "missing_token");
+
+        let basic = bearer_token(&headers("Basic secret")).unwrap_err();
+        assert_eq!(basic.code(), "missing_token");
+    }
+
+    #[test]
+    fn access_token_is_read_from_query() {
+        let uri = Uri::from_static("/ws?types=balance_changed&access_token=secret%20token");
+        assert_eq!(query_token(&uri).as_deref(), Some("secret token"));
+        assert_eq!(query_token(&Uri::from_static("/ws?types=balance_changed")), None);
+        assert_eq!(query_token(&Uri::from_static("/ws")), None);
+    }
+
+    #[test]
+    fn password_must_match_exactly() {
+        assert!(is_password(PASSWORD, PASSWORD));
+        assert!(!is_password("correct horse", PASSWORD));
+        assert!(!is_password(&format!("{PASSWORD} "), PASSWORD));
+        assert!(!is_password("", PASSWORD));
+    }
+
+    #[tokio::test]
+    async fn admin_requires_password() {
+        assert_eq!(status_with_header(None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            status_with_header(Some("Bearer wrong")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status_with_header(Some(&format!("Basic {PASSWORD}"))).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status_with_header(Some(&format!("Bearer {PASSWORD}"))).await,
+            StatusCode::OK
+        );
+    }
+}
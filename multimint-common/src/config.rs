@@ -0,0 +1,470 @@
+//! Server configuration, shared by the multimint servers
+//!
+//! Values are read from, in increasing order of precedence: a TOML file, environment variables and command line flags. The file is given with `--config` or `CONFIG_FILE` and may hold extra sections for server-specific settings, read with `Config::section`.
+//!
+//! Example file:
+//!
+//! ```toml
+//! data_dir = "/var/lib/multimint"
+//! host = "127.0.0.1"
+//! port = 3000
+//! password = "hunter2hunter2"
+//! leave_unlisted = false
+//! meta_refresh_secs = 3600
+//! invite_codes = ["fed11qgqrgvnhwden5te0v9k8q6rp9ekh2arfdeukuet595cr2ttpd3jhq6rzve6zuer9wchxvetyd938gcewvdhk6tcqqysptkuvknc7erjgf4em3zfh90kffqf9srujn6q53d6r056e4apze5cw27h75"]
+//! ```
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::Parser;
+use fedimint_core::api::InviteCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tracing::info;
+
+const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 3000;
+const DEFAULT_META_REFRESH_SECS: u64 = 3600;
+
+/// Command line flags, overriding the environment and the config file
+#[derive(Debug, Clone, Default, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Path to a TOML config file
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Directory holding the multimint and client databases
+    #[arg(long)]
+    pub data_dir: Option<PathBuf>,
+    #[arg(long)]
+    pub host: Option<String>,
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// Password clients authenticate with
+    #[arg(long)]
+    pub password: Option<String>,
+    /// Invite code of a federation to join at startup, may be repeated
+    #[arg(long = "invite-code")]
+    pub invite_codes: Vec<String>,
+    /// Leave the joined federations that are not listed in the invite codes and hold no ecash
+    #[arg(long)]
+    pub leave_unlisted: bool,
+    /// Seconds between refreshes of the federations' metadata, 0 disables refreshing
+    #[arg(long)]
+    pub meta_refresh_secs: Option<u64>,
+}
+
+/// The settings every server reads from its config file
+#[derive(Debug, Clone, Default, Deserialize)]
+struct FileConfig {
+    data_dir: Option<PathBuf>,
+    host: Option<String>,
+    port: Option<u16>,
+    password: Option<String>,
+    #[serde(default)]
+    invite_codes: Vec<String>,
+    #[serde(default)]
+    leave_unlisted: bool,
+    meta_refresh_secs: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Missing(&'static str),
+    Invalid { field: String, reason: String },
+}
+
+impl ConfigError {
+    fn invalid(field: impl Into<String>, reason: impl ToString) -> Self {
+        ConfigError::Invalid {
+            field: field.into(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "Failed to read {}: {e}", path.display()),
+            ConfigError::Parse(path, e) => write!(f, "Failed to parse {}: {e}", path.display()),
+            ConfigError::Missing(field) => write!(f, "{field} must be set"),
+            ConfigError::Invalid { field, reason } => write!(f, "Invalid {field}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub data_dir: PathBuf,
+    pub host: String,
+    pub port: u16,
+    pub password: String,
+    /// Federations to join at startup, without duplicates
+    pub invite_codes: Vec<InviteCode>,
+    /// Leave the federations not listed in `invite_codes` at startup, unless they still hold ecash
+    pub leave_unlisted: bool,
+    /// Seconds between refreshes of the federations' metadata, 0 disables refreshing
+    pub meta_refresh_secs: u64,
+    file: toml::Table,
+}
+
+impl Config {
+    /// Load the config from the command line, the environment (including a `.env` file) and the config file.
+    pub fn load() -> Result<Self, ConfigError> {
+        dotenv::dotenv().ok();
+        Self::from_sources(Cli::parse(), |key| env::var(key).ok())
+    }
+
+    /// Build the config from command line flags and an environment lookup.
+    pub fn from_sources(
+        cli: Cli,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let file_path = cli
+            .config
+            .clone()
+            .or_else(|| env("CONFIG_FILE").map(PathBuf::from));
+        let file = match &file_path {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| ConfigError::Read(path.clone(), e))?;
+                toml::from_str::<toml::Table>(&contents)
+                    .map_err(|e| ConfigError::Parse(path.clone(), e))?
+            }
+            None => toml::Table::new(),
+        };
+        let file_config = toml::Value::Table(file.clone())
+            .try_into::<FileConfig>()
+            .map_err(|e| ConfigError::invalid("config file", e))?;
+
+        let data_dir = cli
+            .data_dir
+            .or_else(|| env("DATA_DIR").map(PathBuf::from))
+            .or(file_config.data_dir)
+            .ok_or(ConfigError::Missing("DATA_DIR"))?;
+
+        let host = cli
+            .host
+            .or_else(|| env("HOST"))
+            .or(file_config.host)
+            .unwrap_or_else(|| DEFAULT_HOST.to_string());
+
+        let port = match cli.port {
+            Some(port) => port,
+            None => match env("PORT") {
+                Some(port) => u16::from_str(&port).map_err(|e| ConfigError::invalid("PORT", e))?,
+                None => file_config.port.unwrap_or(DEFAULT_PORT),
+            },
+        };
+
+        let password = cli
+            .password
+            .or_else(|| env("PASSWORD"))
+            .or(file_config.password)
+            .ok_or(ConfigError::Missing("PASSWORD"))?;
+
+        // `INVITE_CODE` is the single invite code older deployments set
+        let invite_codes = if !cli.invite_codes.is_empty() {
+            cli.invite_codes
+        } else if let Some(invite_codes) = env("INVITE_CODES").or_else(|| env("INVITE_CODE")) {
+            invite_codes
+                .split(',')
+                .map(str::trim)
+                .filter(|invite_code| !invite_code.is_empty())
+                .map(str::to_string)
+                .collect()
+        } else {
+            file_config.invite_codes
+        };
+
+        let leave_unlisted = if cli.leave_unlisted {
+            true
+        } else {
+            match env("LEAVE_UNLISTED") {
+                Some(leave_unlisted) => bool::from_str(&leave_unlisted)
+                    .map_err(|e| ConfigError::invalid("LEAVE_UNLISTED", e))?,
+                None => file_config.leave_unlisted,
+            }
+        };
+
+        let meta_refresh_secs = match cli.meta_refresh_secs {
+            Some(secs) => secs,
+            None => match env("META_REFRESH_SECS") {
+                Some(secs) => u64::from_str(&secs)
+                    .map_err(|e| ConfigError::invalid("META_REFRESH_SECS", e))?,
+                None => file_config
+                    .meta_refresh_secs
+                    .unwrap_or(DEFAULT_META_REFRESH_SECS),
+            },
+        };
+
+        let config = Self {
+            data_dir,
+            host,
+            port,
+            password,
+            invite_codes: parse_invite_codes(&invite_codes)?,
+            leave_unlisted,
+            meta_refresh_secs,
+            file,
+        };
+        config.validate()?;
+
+        info!("Loaded config");
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.data_dir.is_file() {
+            return Err(ConfigError::invalid(
+                "DATA_DIR",
+                format!("{} is a file", self.data_dir.display()),
+            ));
+        }
+        if self.host.trim().is_empty() {
+            return Err(ConfigError::invalid("HOST", "must not be empty"));
+        }
+        if self.port == 0 {
+            return Err(ConfigError::invalid("PORT", "must not be 0"));
+        }
+        if self.password.trim().is_empty() {
+            return Err(ConfigError::invalid("PASSWORD", "must not be empty"));
+        }
+        // Guards against leaving every federation because the invite codes went missing from the config
+        if self.leave_unlisted && self.invite_codes.is_empty() {
+            return Err(ConfigError::invalid(
+                "LEAVE_UNLISTED",
+                "requires at least one invite code",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The address the server listens on
+    pub fn bind_address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    /// How often to refresh the federations' metadata, `None` if refreshing is disabled
+    pub fn meta_refresh_interval(&self) -> Option<Duration> {
+        (self.meta_refresh_secs > 0).then_some(Duration::from_secs(self.meta_refresh_secs))
+    }
+
+    /// Deserialize a server-specific section of the config file, falling back to its default when the section is absent.
+    pub fn section<T: DeserializeOwned + Default>(&self, name: &str) -> Result<T, ConfigError> {
+        match self.file.get(name) {
+            Some(section) => section
+                .clone()
+                .try_into()
+                .map_err(|e| ConfigError::invalid(format!("[{name}]"), e)),
+            None => Ok(T::default()),
+        }
+    }
+}
+
+/// Parse invite codes, dropping the ones for a federation that is already listed
+fn parse_invite_codes(invite_codes: &[String]) -> Result<Vec<InviteCode>, ConfigError> {
+    let mut federation_ids = BTreeSet::new();
+    let mut parsed = Vec::new();
+
+    for invite_code in invite_codes {
+        let invite_code = InviteCode::from_str(invite_code)
+            .map_err(|e| ConfigError::invalid(format!("invite code {invite_code}"), e))?;
+        if federation_ids.insert(invite_code.federation_id()) {
+            parsed.push(invite_code);
+        }
+    }
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    const INVITE_CODE: &str = "fed11qgqrgvnhwden5te0v9k8q6rp9ekh2arfdeukuet595cr2ttpd3jhq6rzve6zuer9wchxvetyd938gcewvdhk6tcqqysptkuvknc7erjgf4em3zfh90kffqf9srujn6q53d6r056e4apze5cw27h75";
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<BTreeMap<_, _>>();
+        move |key| vars.get(key).cloned()
+    }
+
+    fn config_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "multimint-config-{}-{name}.toml",
+            std::process::id()
+        ));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn required_env<'a>() -> Vec<(&'a str, &'a str)> {
+        vec![
+            ("DATA_DIR", "/nonexistent/multimint"),
+            ("PASSWORD", "password"),
+        ]
+    }
+
+    #[test]
+    fn cli_overrides_env_overrides_file() {
+        let file = config_file(
+            "precedence",
+            r#"
+                data_dir = "/nonexistent/file"
+                host = "file-host"
+                port = 1000
+                password = "file-password"
+
+                [swap]
+                quote_ttl_secs = 30
+            "#,
+        );
+        let cli = Cli {
+            config: Some(file.clone()),
+            host: Some("cli-host".to_string()),
+            ..Cli::default()
+        };
+
+        let config =
+            Config::from_sources(cli, env(&[("HOST", "env-host"), ("PORT", "2000")])).unwrap();
+        std::fs::remove_file(file).unwrap();
+
+        assert_eq!(config.host, "cli-host");
+        assert_eq!(config.port, 2000);
+        assert_eq!(config.password, "file-password");
+        assert_eq!(config.data_dir, PathBuf::from("/nonexistent/file"));
+        assert_eq!(
+            config.section::<toml::Table>("swap").unwrap()["quote_ttl_secs"],
+            toml::Value::Integer(30)
+        );
+    }
+
+    #[test]
+    fn defaults_apply_without_file() {
+        let config = Config::from_sources(Cli::default(), env(&required_env())).unwrap();
+
+        assert_eq!(config.bind_address(), "127.0.0.1:3000");
+        assert!(config.invite_codes.is_empty());
+        assert!(!config.leave_unlisted);
+        assert_eq!(
+            config.meta_refresh_interval(),
+            Some(Duration::from_secs(DEFAULT_META_REFRESH_SECS))
+        );
+        assert!(config.section::<toml::Table>("swap").unwrap().is_empty());
+    }
+
+    #[test]
+    fn required_fields_must_be_set() {
+        assert!(matches!(
+            Config::from_sources(Cli::default(), env(&[("PASSWORD", "password")])),
+            Err(ConfigError::Missing("DATA_DIR"))
+        ));
+        assert!(matches!(
+            Config::from_sources(Cli::default(), env(&[("DATA_DIR", "/nonexistent")])),
+            Err(ConfigError::Missing("PASSWORD"))
+        ));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let cases = [
+            ("PORT", "not a port", "PORT"),
+            ("PORT", "0", "PORT"),
+            ("HOST", " ", "HOST"),
+            ("PASSWORD", " ", "PASSWORD"),
+            ("LEAVE_UNLISTED", "maybe", "LEAVE_UNLISTED"),
+            ("LEAVE_UNLISTED", "true", "LEAVE_UNLISTED"),
+            ("META_REFRESH_SECS", "-1", "META_REFRESH_SECS"),
+        ];
+
+        for (key, value, invalid_field) in cases {
+            let mut vars = required_env();
+            vars.push((key, value));
+            match Config::from_sources(Cli::default(), env(&vars)) {
+                Err(ConfigError::Invalid { field, .. }) => {
+                    assert_eq!(field, invalid_field, "{key}={value}")
+                }
+                other => panic!("{key}={value} was not rejected: {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn meta_refresh_can_be_disabled() {
+        let mut vars = required_env();
+        vars.push(("META_REFRESH_SECS", "0"));
+        let config = Config::from_sources(Cli::default(), env(&vars)).unwrap();
+        assert_eq!(config.meta_refresh_interval(), None);
+
+        let cli = Cli {
+            meta_refresh_secs: Some(60),
+            ..Cli::default()
+        };
+        let config = Config::from_sources(cli, env(&vars)).unwrap();
+        assert_eq!(config.meta_refresh_interval(), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn data_dir_must_not_be_a_file() {
+        let file = config_file("data-dir", "");
+        let cli = Cli {
+            data_dir: Some(file.clone()),
+            ..Cli::default()
+        };
+
+        let result = Config::from_sources(cli, env(&required_env()));
+        std::fs::remove_file(file).unwrap();
+
+        assert!(matches!(result, Err(ConfigError::Invalid { field, .. }) if field == "DATA_DIR"));
+    }
+
+    #[test]
+    fn invite_codes_are_read_from_env_list() {
+        let mut vars = required_env();
+        vars.push(("INVITE_CODES", " , "));
+        vars.push(("LEAVE_UNLISTED", "false"));
+        let config = Config::from_sources(Cli::default(), env(&vars)).unwrap();
+        assert!(config.invite_codes.is_empty());
+
+        let invite_codes = format!("{INVITE_CODE}, {INVITE_CODE},");
+        let mut vars = required_env();
+        vars.push(("INVITE_CODES", &invite_codes));
+        vars.push(("LEAVE_UNLISTED", "true"));
+        let config = Config::from_sources(Cli::default(), env(&vars)).unwrap();
+        assert_eq!(config.invite_codes.len(), 1);
+        assert!(config.leave_unlisted);
+    }
+
+    #[test]
+    fn duplicate_invite_codes_are_dropped() {
+        let invite_codes =
+            parse_invite_codes(&[INVITE_CODE.to_string(), INVITE_CODE.to_string()]).unwrap();
+        assert_eq!(invite_codes.len(), 1);
+        assert_eq!(invite_codes[0], InviteCode::from_str(INVITE_CODE).unwrap());
+    }
+
+    #[test]
+    fn invalid_invite_code_is_rejected() {
+        assert!(matches!(
+            parse_invite_codes(&[INVITE_CODE.to_string(), "fed11invalid".to_string()]),
+            Err(ConfigError::Invalid { .. })
+        ));
+        assert!(parse_invite_codes(&[]).unwrap().is_empty());
+    }
+}
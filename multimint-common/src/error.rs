@@ -0,0 +1,233 @@
+use std::fmt;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use multimint::error::MultiMintError;
+use serde_json::{json, Value};
+
+/// An error answered as a JSON body of the form `{ "code", "message", "status", "details" }`.
+///
+/// `code` is a stable, machine-readable string: the code set with `with_code`, else the code of the `MultiMintError` that caused the error, else one derived from the status.
+pub struct AppError {
+    pub error: anyhow::Error,
+    pub status: StatusCode,
+    pub code: Option<&'static str>,
+    pub details: Option<Value>,
+}
+
+impl AppError {
+    pub fn new(status: StatusCode, error: impl Into<anyhow::Error>) -> Self {
+        Self {
+            error: error.into(),
+            status,
+            code: None,
+            details: None,
+        }
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+            .or_else(|| MultiMintError::find(&self.error).map(MultiMintError::code))
+            .unwrap_or_else(|| status_code(self.status))
+    }
+
+    pub fn details(&self) -> Option<Value> {
+        self.details
+            .clone()
+            .or_else(|| MultiMintError::find(&self.error).and_then(MultiMintError::details))
+    }
+
+    fn to_json(&self) -> Value {
+        let mut body = json!({
+            "code": self.code(),
+            "message": self.error.to_string(),
+            "status": self.status.as_u16(),
+        });
+        if let Some(details) = self.details() {
+            body["details"] = details;
+        }
+        body
+    }
+}
+
+/// The status library errors are answered with when a handler doesn't pick one
+fn library_status(error: &MultiMintError) -> StatusCode {
+    match error {
+        MultiMintError::FederationNotFound(_) | MultiMintError::OperationNotFound(_) => {
+            StatusCode::NOT_FOUND
+        }
+        MultiMintError::InvalidSecret(_)
+        | MultiMintError::SameFederation
+        | MultiMintError::InsufficientBalance { .. }
+        | MultiMintError::WrongNetwork { .. } => StatusCode::BAD_REQUEST,
+        MultiMintError::MissingModule(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        MultiMintError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+    }
+}
+
+fn status_code(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::UNPROCESSABLE_ENTITY => "unprocessable",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+        StatusCode::BAD_GATEWAY => "upstream_failed",
+        StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+        StatusCode::GATEWAY_TIMEOUT => "upstream_timeout",
+        _ if status.is_client_error() => "bad_request",
+        _ => "internal_error",
+    }
+}
+
+// Tell axum how to convert `AppError` into a response.
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.to_json())).into_response()
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_json())
+    }
+}
+
+impl<E> From<E> for AppError
+where
+    E: Into<anyhow::Error>,
+{
+    fn from(err: E) -> Self {
+        let error = err.into();
+        let status = MultiMintError::find(&error)
+            .map(library_status)
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+
+        Self::new(status, error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use axum::body::to_bytes;
+    use fedimint_core::core::OperationId;
+    use fedimint_core::Amount;
+    use multimint::test_utils::{check_cases, federation_id};
+
+    use super::*;
+
+    async fn response_body(error: AppError) -> (StatusCode, Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn not_found_errors_map_to_404() {
+        let cases = [
+            (
+                MultiMintError::FederationNotFound(federation_id(1)),
+                (StatusCode::NOT_FOUND, "federation_not_found"),
+            ),
+            (
+                MultiMintError::OperationNotFound(OperationId::new_random()),
+                (StatusCode::NOT_FOUND, "operation_not_found"),
+            ),
+        ];
+
+        check_cases(cases, |error| {
+            let error = AppError::from(anyhow::Error::from(error));
+            (error.status, error.code())
+        });
+    }
+
+    #[test]
+    fn insufficient_balance_maps_to_400_with_details() {
+        let error = AppError::from(
+            anyhow::Error::from(MultiMintError::InsufficientBalance {
+                federation_id: federation_id(1),
+                needed: Amount::from_sats(2),
+                available: Amount::from_sats(1),
+            })
+            .context("Failed to pay invoice"),
+        );
+
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.code(), "insufficient_balance");
+        let details = error.details().unwrap();
+        assert_eq!(details["needed_msat"], 2_000);
+        assert_eq!(details["available_msat"], 1_000);
+    }
+
+    #[test]
+    fn other_errors_map_to_500() {
+        let error = AppError::from(anyhow!("Something broke"));
+        assert_eq!(error.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.code(), "internal_error");
+        assert!(error.details().is_none());
+    }
+
+    #[test]
+    fn explicit_code_wins_over_library_code() {
+        let error = AppError::new(
+            StatusCode::CONFLICT,
+            MultiMintError::FederationNotFound(federation_id(1)),
+        )
+        .with_code("quote_already_used");
+        assert_eq!(error.code(), "quote_already_used");
+    }
+
+    #[tokio::test]
+    async fn body_holds_code_message_status_and_details() {
+        let error = AppError::new(StatusCode::BAD_REQUEST, anyhow!("Amount too small"))
+            .with_code("amount_out_of_range")
+            .with_details(json!({ "min_amount": 1_000 }));
+
+        let (status, body) = response_body(error).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body,
+            json!({
+                "code": "amount_out_of_range",
+                "message": "Amount too small",
+                "status": 400,
+                "details": { "min_amount": 1_000 },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn body_omits_missing_details() {
+        let (status, body) = response_body(AppError::new(
+            StatusCode::NOT_FOUND,
+            anyhow!("Unknown swap"),
+        ))
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            body,
+            json!({
+                "code": "not_found",
+                "message": "Unknown swap",
+                "status": 404,
+            })
+        );
+    }
+}
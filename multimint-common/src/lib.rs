@@ -0,0 +1,7 @@
+//! # Multimint Common
+//!
+//! Building blocks shared by `multimint-server` and `multimint-swap`.
+
+pub mod auth;
+pub mod config;
+pub mod error;
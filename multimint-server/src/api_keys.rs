@@ -0,0 +1,246 @@
+use anyhow::{anyhow, Result};
+use fedimint_core::bitcoin_hashes::{sha256, Hash};
+use fedimint_core::config::FederationId;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::Amount;
+use futures_util::StreamExt;
+use rand::RngCore;
+use subtle::ConstantTimeEq;
+
+use crate::db::{ApiKey, ApiKeyKey, ApiKeyKeyPrefix, Scope};
+
+/// Prefix of every API key token, which looks like `mm_<id>_<secret>`
+pub const TOKEN_PREFIX: &str = "mm";
+
+/// API keys stored in the multimint database
+#[derive(Debug, Clone)]
+pub struct ApiKeyStore {
+    db: Database,
+}
+
+impl ApiKeyStore {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Create a new API key, returning its id and the token to hand to the client. The token cannot be recovered later.
+    pub async fn create(
+        &self,
+        name: String,
+        scope: Scope,
+        federations: Option<Vec<FederationId>>,
+        spend_limit_msat: Option<Amount>,
+    ) -> Result<(String, String, ApiKey)> {
+        let mut id = [0u8; 8];
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut id);
+        rand::thread_rng().fill_bytes(&mut secret);
+        let id = hex::encode(id);
+        let secret = hex::encode(secret);
+
+        let key = ApiKey {
+            name,
+            scope,
+            federations,
+            spend_limit_msat,
+            spent_msat: Amount::ZERO,
+            created_at: unix_now(),
+            secret_hash: hash_secret(&secret),
+        };
+
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_new_entry(&ApiKeyKey { id: id.clone() }, &key)
+            .await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| anyhow!("Failed to save API key: {:?}", e))?;
+
+        let token = format!("{TOKEN_PREFIX}_{id}_{secret}");
+        Ok((id, token, key))
+    }
+
+    pub async fn list(&self) -> Vec<(String, ApiKey)> {
+        self.db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&ApiKeyKeyPrefix)
+            .await
+            .map(|(key, api_key)| (key.id, api_key))
+            .collect::<Vec<_>>()
+            .await
+    }
+
+    /// Revoke an API key, returning whether it existed
+    pub async fn revoke(&self, id: &str) -> Result<bool> {
+        let mut dbtx = self.db.begin_transaction().await;
+        let existed = dbtx
+            .remove_entry(&ApiKeyKey { id: id.to_string() })
+            .await
+            .is_some();
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| anyhow!("Failed to revoke API key: {:?}", e))?;
+        Ok(existed)
+    }
+
+    /// Look up the API key a token belongs to, comparing secrets in constant time
+    pub async fn authenticate(&self, token: &str) -> Option<(String, ApiKey)> {
+        let (id, secret) = token
+            .strip_prefix(TOKEN_PREFIX)?
+            .strip_prefix('_')?
+            .split_once('_')?;
+
+        let key = self
+            .db
+            .begin_transaction_nc()
+            .await
+            .get_value(&ApiKeyKey { id: id.to_string() })
+            .await?;
+
+        if bool::from(hash_secret(secret).ct_eq(&key.secret_hash)) {
+            Some((id.to_string(), key))
+        } else {
+            None
+        }
+    }
+
+    /// Add `amount` to what an API key has spent, failing without recording anything if it would exceed the key's spend limit
+    pub async fn record_spend(&self, id: &str, amount: Amount) -> Result<()> {
+        let db_key = ApiKeyKey { id: id.to_string() };
+        let mut dbtx = self.db.begin_transaction().await;
+        let mut key = dbtx
+            .get_value(&db_key)
+            .await
+            .ok_or_else(|| anyhow!("API key {id} was revoked"))?;
+
+        let spent_msat = key.spent_msat + amount;
+        if let Some(limit) = key.spend_limit_msat {
+            if spent_msat > limit {
+                return Err(anyhow!(
+                    "Spending {amount} would exceed the API key's spend limit of {limit}, {} already spent",
+                    key.spent_msat
+                ));
+            }
+        }
+
+        key.spent_msat = spent_msat;
+        dbtx.insert_entry(&db_key, &key).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| anyhow!("Failed to record API key spend: {:?}", e))
+    }
+
+    /// Give back a spend recorded with `record_spend` whose payment did not go through
+    pub async fn release_spend(&self, id: &str, amount: Amount) -> Result<()> {
+        let db_key = ApiKeyKey { id: id.to_string() };
+        let mut dbtx = self.db.begin_transaction().await;
+        let Some(mut key) = dbtx.get_value(&db_key).await else {
+            return Ok(());
+        };
+
+        key.spent_msat = Amount::from_msats(key.spent_msat.msats.saturating_sub(amount.msats));
+        dbtx.insert_entry(&db_key, &key).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| anyhow!("Failed to release API key spend: {:?}", e))
+    }
+}
+
+fn hash_secret(secret: &str) -> [u8; 32] {
+    sha256::Hash::hash(secret.as_bytes()).into_inner()
+}
+
+fn unix_now() -> u64 {
+    fedimint_core::time::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::api_keys;
+
+    async fn spent(api_keys: &ApiKeyStore, id: &str) -> Amount {
+        let keys = api_keys.list().await;
+        let (_, key) = keys.iter().find(|(key_id, _)| key_id == id).unwrap();
+        key.spent_msat
+    }
+
+    #[tokio::test]
+    async fn spends_are_capped_by_the_limit() {
+        let api_keys = api_keys();
+        let (id, _, _) = api_keys
+            .create(
+                "spender".to_string(),
+                Scope::Spend,
+                None,
+                Some(Amount::from_sats(1_000)),
+            )
+            .await
+            .unwrap();
+
+        api_keys
+            .record_spend(&id, Amount::from_sats(600))
+            .await
+            .unwrap();
+        assert!(api_keys
+            .record_spend(&id, Amount::from_sats(401))
+            .await
+            .is_err());
+        assert_eq!(spent(&api_keys, &id).await, Amount::from_sats(600));
+
+        api_keys
+            .record_spend(&id, Amount::from_sats(400))
+            .await
+            .unwrap();
+        assert_eq!(spent(&api_keys, &id).await, Amount::from_sats(1_000));
+    }
+
+    #[tokio::test]
+    async fn released_spends_free_the_limit() {
+        let api_keys = api_keys();
+        let (id, _, _) = api_keys
+            .create(
+                "spender".to_string(),
+                Scope::Spend,
+                None,
+                Some(Amount::from_sats(1_000)),
+            )
+            .await
+            .unwrap();
+
+        api_keys
+            .record_spend(&id, Amount::from_sats(1_000))
+            .await
+            .unwrap();
+        api_keys
+            .release_spend(&id, Amount::from_sats(1_000))
+            .await
+            .unwrap();
+        assert_eq!(spent(&api_keys, &id).await, Amount::ZERO);
+
+        // Releasing more than was spent never goes below nothing spent
+        api_keys
+            .release_spend(&id, Amount::from_sats(1))
+            .await
+            .unwrap();
+        assert_eq!(spent(&api_keys, &id).await, Amount::ZERO);
+    }
+
+    #[tokio::test]
+    async fn revoked_key_cannot_spend() {
+        let api_keys = api_keys();
+        let (id, _, _) = api_keys
+            .create("spender".to_string(), Scope::Spend, None, None)
+            .await
+            .unwrap();
+        assert!(api_keys.revoke(&id).await.unwrap());
+
+        assert!(api_keys
+            .record_spend(&id, Amount::from_sats(1))
+            .await
+            .is_err());
+    }
+}
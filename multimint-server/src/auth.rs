@@ -0,0 +1,383 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use fedimint_core::{config::FederationId, Amount};
+use multimint_common::auth::{bearer_token, invalid_token, is_password, query_token};
+
+use crate::{api_keys::ApiKeyStore, db::ApiKey, db::Scope, error::AppError};
+
+/// State for the bearer token middleware: the master password and the API keys clients may present instead
+#[derive(Debug, Clone)]
+pub struct AuthState {
+    password: Arc<str>,
+    api_keys: ApiKeyStore,
+}
+
+impl AuthState {
+    pub fn new(password: impl Into<Arc<str>>, api_keys: ApiKeyStore) -> Self {
+        Self {
+            password: password.into(),
+            api_keys,
+        }
+    }
+
+    /// State for a middleware layer requiring at least `scope`
+    pub fn require(&self, scope: Scope) -> ScopedAuthState {
+        ScopedAuthState {
+            auth: self.clone(),
+            scope,
+            accepts_query_token: false,
+        }
+    }
+
+    async fn authenticate(&self, token: &str) -> Option<AuthContext> {
+        if is_password(token, &self.password) {
+            return Some(AuthContext::Master);
+        }
+
+        self.api_keys
+            .authenticate(token)
+            .await
+            .map(|(id, key)| AuthContext::ApiKey { id, key })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScopedAuthState {
+    auth: AuthState,
+    scope: Scope,
+    accepts_query_token: bool,
+}
+
+impl ScopedAuthState {
+    /// Also accept the token as `access_token` query parameter, for routes browsers open as WebSocket, which can't carry an `Authorization` header
+    pub fn with_query_token(mut self) -> Self {
+        self.accepts_query_token = true;
+        self
+    }
+}
+
+/// Who made a request, added to the request extensions by `require_scope`
+#[derive(Debug, Clone)]
+pub enum AuthContext {
+    /// Authenticated with the server password, which has every permission
+    Master,
+    ApiKey { id: String, key: ApiKey },
+}
+
+impl AuthContext {
+    pub fn scope(&self) -> Scope {
+        match self {
+            AuthContext::Master => Scope::Admin,
+            AuthContext::ApiKey { key, .. } => key.scope,
+        }
+    }
+
+    pub fn can_access(&self, federation_id: &FederationId) -> bool {
+        match self {
+            AuthContext::Master => true,
+            AuthContext::ApiKey { key, .. } => key
+                .federations
+                .as_ref()
+                .map_or(true, |federations| federations.contains(federation_id)),
+        }
+    }
+
+    /// Reject API keys restricted to other federations
+    pub fn check_federation(&self, federation_id: &FederationId) -> Result<(), AppError> {
+        if !self.can_access(federation_id) {
+            return Err(AppError::new(
+                StatusCode::FORBIDDEN,
+                anyhow!("API key is not allowed to access federation {federation_id}"),
+            )
+            .with_code("federation_forbidden"));
+        }
+        Ok(())
+    }
+
+    /// Reject API keys that would grant more than the caller holds: a wider scope, other federations or a higher spend limit than it has left
+    pub fn check_delegation(
+        &self,
+        scope: Scope,
+        federations: Option<&[FederationId]>,
+        spend_limit: Option<Amount>,
+    ) -> Result<(), AppError> {
+        let AuthContext::ApiKey { key, .. } = self else {
+            return Ok(());
+        };
+
+        let escalation = |reason: String| {
+            AppError::new(StatusCode::FORBIDDEN, anyhow!(reason)).with_code("api_key_escalation")
+        };
+
+        if !key.scope.allows(scope) {
+            return Err(escalation(format!(
+                "Cannot grant the {scope:?} scope with a {:?} API key",
+                key.scope
+            )));
+        }
+
+        if let Some(allowed) = &key.federations {
+            match federations {
+                None => {
+                    return Err(escalation(
+                        "API key restricted to some federations cannot grant access to all of them"
+                            .to_string(),
+                    ))
+                }
+                Some(federations) => {
+                    if let Some(federation_id) = federations
+                        .iter()
+                        .find(|federation_id| !allowed.contains(federation_id))
+                    {
+                        return Err(escalation(format!(
+                            "API key is not allowed to access federation {federation_id}"
+                        )));
+                    }
+                }
+            }
+        }
+
+        if let Some(limit) = key.spend_limit_msat {
+            let remaining = limit.saturating_sub(key.spent_msat);
+            match spend_limit {
+                Some(spend_limit) if spend_limit <= remaining => {}
+                _ => {
+                    return Err(escalation(format!(
+                        "API key can grant a spend limit of at most {remaining}"
+                    )))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Count `amount` against the API key's spend limit, rejecting the spend if the limit would be exceeded
+    pub async fn record_spend(&self, api_keys: &ApiKeyStore, amount: Amount) -> Result<(), AppError> {
+        match self {
+            AuthContext::Master => Ok(()),
+            AuthContext::ApiKey { id, .. } => api_keys
+                .record_spend(id, amount)
+                .await
+                .map_err(|e| {
+                    AppError::new(StatusCode::FORBIDDEN, e).with_code("spend_limit_exceeded")
+                }),
+        }
+    }
+
+    /// Undo `record_spend` after the payment it was recorded for failed
+    pub async fn release_spend(&self, api_keys: &ApiKeyStore, amount: Amount) -> Result<(), AppError> {
+        match self {
+            AuthContext::Master => Ok(()),
+            AuthContext::ApiKey { id, .. } => Ok(api_keys.release_spend(id, amount).await?),
+        }
+    }
+}
+
+/// Reject requests that don't carry an `Authorization: Bearer <token>` header, or on routes accepting it an `access_token` query parameter, with the password or an API key of at least the required scope
+pub async fn require_scope(
+    State(state): State<ScopedAuthState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let token = match bearer_token(request.headers()) {
+        Ok(token) => token.to_string(),
+        Err(missing) if state.accepts_query_token => query_token(request.uri()).ok_or(missing)?,
+        Err(missing) => return Err(missing),
+    };
+    let context = state
+        .auth
+        .authenticate(&token)
+        .await
+        .ok_or_else(invalid_token)?;
+
+    if !context.scope().allows(state.scope) {
+        return Err(AppError::new(
+            StatusCode::FORBIDDEN,
+            anyhow!("This route requires the {:?} scope", state.scope),
+        )
+        .with_code("insufficient_scope"));
+    }
+
+    request.extensions_mut().insert(context);
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request, StatusCode},
+        middleware,
+        routing::post,
+        Router,
+    };
+    use multimint::test_utils::federation_id;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_utils::api_keys;
+
+    const PASSWORD: &str = "correct horse battery staple";
+
+    fn app(api_keys: ApiKeyStore, scope: Scope) -> Router {
+        let auth = AuthState::new(PASSWORD, api_keys);
+        Router::new()
+            .route("/mutate", post(|| async { "ok" }))
+            .route_layer(middleware::from_fn_with_state(
+                auth.require(scope),
+                require_scope,
+            ))
+    }
+
+    async fn status_with_token(app: Router, token: &str) -> StatusCode {
+        let request = Request::post("/mutate")
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn password_has_the_admin_scope() {
+        assert_eq!(
+            status_with_token(app(api_keys(), Scope::Admin), PASSWORD).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn api_key_scope_is_enforced() {
+        let api_keys = api_keys();
+        let (_, token, _) = api_keys
+            .create("reader".to_string(), Scope::ReadOnly, None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            status_with_token(app(api_keys.clone(), Scope::ReadOnly), &token).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status_with_token(app(api_keys, Scope::Spend), &token).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn query_token_is_only_accepted_where_enabled() {
+        let auth = AuthState::new(PASSWORD, api_keys());
+        let uri = format!("/ws?access_token={}", PASSWORD.replace(' ', "%20"));
+
+        for (scoped, expected) in [
+            (auth.require(Scope::ReadOnly).with_query_token(), StatusCode::OK),
+            (auth.require(Scope::ReadOnly), StatusCode::UNAUTHORIZED),
+        ] {
+            let app = Router::new()
+                .route("/ws", axum::routing::get(|| async { "ok" }))
+                .route_layer(middleware::from_fn_with_state(scoped, require_scope));
+            let response = app
+                .oneshot(Request::get(&uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn revoked_api_key_is_rejected() {
+        let api_keys = api_keys();
+        let (id, token, _) = api_keys
+            .create("admin".to_string(), Scope::Admin, None, None)
+            .await
+            .unwrap();
+        assert!(api_keys.revoke(&id).await.unwrap());
+
+        assert_eq!(
+            status_with_token(app(api_keys, Scope::ReadOnly), &token).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    fn api_key_context(
+        scope: Scope,
+        federations: Option<Vec<FederationId>>,
+        spend_limit_msat: Option<Amount>,
+    ) -> AuthContext {
+        AuthContext::ApiKey {
+            id: "creator".to_string(),
+            key: ApiKey {
+                name: "creator".to_string(),
+                scope,
+                federations,
+                spend_limit_msat,
+                spent_msat: Amount::from_sats(400),
+                created_at: 0,
+                secret_hash: [0; 32],
+            },
+        }
+    }
+
+    fn is_escalation(result: Result<(), AppError>) -> bool {
+        matches!(result, Err(e) if e.code() == "api_key_escalation")
+    }
+
+    #[test]
+    fn master_can_delegate_anything() {
+        assert!(AuthContext::Master
+            .check_delegation(Scope::Admin, None, None)
+            .is_ok());
+    }
+
+    #[test]
+    fn restricted_admin_key_cannot_create_unrestricted_keys() {
+        let federation = federation_id(1);
+        let other = federation_id(2);
+        let creator = api_key_context(
+            Scope::Admin,
+            Some(vec![federation]),
+            Some(Amount::from_sats(1_000)),
+        );
+
+        assert!(is_escalation(creator.check_delegation(
+            Scope::Admin,
+            None,
+            Some(Amount::from_sats(100))
+        )));
+        assert!(is_escalation(creator.check_delegation(
+            Scope::Spend,
+            Some(&[federation, other]),
+            Some(Amount::from_sats(100))
+        )));
+        assert!(is_escalation(creator.check_delegation(
+            Scope::Spend,
+            Some(&[federation]),
+            None
+        )));
+        // Only what is left of the creator's own limit can be granted
+        assert!(is_escalation(creator.check_delegation(
+            Scope::Spend,
+            Some(&[federation]),
+            Some(Amount::from_sats(700))
+        )));
+        assert!(creator
+            .check_delegation(Scope::Spend, Some(&[federation]), Some(Amount::from_sats(600)))
+            .is_ok());
+    }
+
+    #[test]
+    fn scope_cannot_exceed_the_creators() {
+        let creator = api_key_context(Scope::Spend, None, None);
+
+        assert!(is_escalation(creator.check_delegation(Scope::Admin, None, None)));
+        assert!(creator.check_delegation(Scope::Receive, None, None).is_ok());
+    }
+}
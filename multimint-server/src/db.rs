@@ -0,0 +1,67 @@
+use fedimint_core::config::FederationId;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount};
+use serde::{Deserialize, Serialize};
+
+/// Key prefixes of the server's records in the multimint database, starting at the range `multimint` leaves to applications
+#[repr(u8)]
+#[derive(Clone, Debug)]
+pub enum DbKeyPrefix {
+    ApiKey = 0x20,
+}
+
+impl std::fmt::Display for DbKeyPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// What an API key is allowed to do. Each scope includes the ones before it.
+#[derive(
+    Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Encodable, Decodable, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    ReadOnly,
+    Receive,
+    Spend,
+    Admin,
+}
+
+impl Scope {
+    pub fn allows(&self, required: Scope) -> bool {
+        *self >= required
+    }
+}
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct ApiKeyKey {
+    pub id: String,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ApiKeyKeyPrefix;
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub name: String,
+    pub scope: Scope,
+    /// Federations the key may act on, all of them if `None`
+    pub federations: Option<Vec<FederationId>>,
+    /// Total amount the key may spend over its lifetime, unlimited if `None`
+    pub spend_limit_msat: Option<Amount>,
+    pub spent_msat: Amount,
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+    /// SHA256 of the key's secret, the secret itself is only shown once on creation
+    #[serde(skip)]
+    pub secret_hash: [u8; 32],
+}
+
+impl_db_record!(
+    key = ApiKeyKey,
+    value = ApiKey,
+    db_prefix = DbKeyPrefix::ApiKey,
+);
+
+impl_db_lookup!(key = ApiKeyKey, query_prefix = ApiKeyKeyPrefix);
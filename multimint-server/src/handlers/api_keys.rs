@@ -0,0 +1,95 @@
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use fedimint_core::{config::FederationId, Amount};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{auth::AuthContext, db::Scope, error::AppError, AppState};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateApiKeyPayload {
+    pub name: String,
+    pub scope: Scope,
+    pub federations: Option<Vec<FederationId>>,
+    pub spend_limit_msat: Option<Amount>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_create_api_key(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(req): Json<CreateApiKeyPayload>,
+) -> Result<Json<Value>, AppError> {
+    auth.check_delegation(req.scope, req.federations.as_deref(), req.spend_limit_msat)?;
+
+    if let Some(federations) = &req.federations {
+        for federation_id in federations {
+            if !state.multimint.has(federation_id).await {
+                return Err(AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    anyhow!("Unknown federation: {federation_id}"),
+                ));
+            }
+        }
+    }
+
+    // The granted limit comes out of the creating key's own, so it can't be handed out twice
+    if let Some(spend_limit) = req.spend_limit_msat {
+        auth.record_spend(&state.api_keys, spend_limit).await?;
+    }
+
+    let (id, token, key) = match state
+        .api_keys
+        .create(req.name, req.scope, req.federations, req.spend_limit_msat)
+        .await
+    {
+        Ok(created) => created,
+        Err(e) => {
+            if let Some(spend_limit) = req.spend_limit_msat {
+                auth.release_spend(&state.api_keys, spend_limit).await?;
+            }
+            return Err(e.into());
+        }
+    };
+
+    Ok(Json(json!({
+        "id": id,
+        "token": token,
+        "key": key,
+    })))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_list_api_keys(State(state): State<AppState>) -> Result<Json<Value>, AppError> {
+    let keys = state
+        .api_keys
+        .list()
+        .await
+        .into_iter()
+        .map(|(id, key)| json!({ "id": id, "key": key }))
+        .collect::<Vec<_>>();
+
+    Ok(Json(json!(keys)))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_revoke_api_key(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    if !state.api_keys.revoke(&id).await? {
+        return Err(AppError::new(
+            StatusCode::NOT_FOUND,
+            anyhow!("Unknown API key: {id}"),
+        ));
+    }
+
+    Ok(Json(json!({
+        "status": "ok",
+    })))
+}
@@ -0,0 +1,238 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use fedimint_core::{config::FederationId, Amount};
+use fedimint_mint_client::{MintClientModule, OOBNotes, ReissueExternalNotesState};
+use futures_util::StreamExt;
+use multimint::client::first_module;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+
+use super::federations::check_known_federation;
+use crate::{auth::AuthContext, error::AppError, AppState};
+
+/// How long spent notes stay valid before the server tries to reclaim them, if the request doesn't say
+const DEFAULT_SPEND_TIMEOUT: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SpendPayload {
+    pub federation_id: FederationId,
+    pub amount_msat: Amount,
+    /// Seconds after which unclaimed notes are reissued back to the server
+    pub timeout: Option<u64>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_spend(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(req): Json<SpendPayload>,
+) -> Result<Json<Value>, AppError> {
+    check_known_federation(&state, &auth, &req.federation_id).await?;
+    let client = state.multimint.get_or_err(&req.federation_id).await?;
+
+    auth.record_spend(&state.api_keys, req.amount_msat).await?;
+
+    let timeout = req
+        .timeout
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_SPEND_TIMEOUT);
+    let mint_client = first_module::<MintClientModule>(&client)?;
+    let (operation_id, notes) = match mint_client.spend_notes(req.amount_msat, timeout, ()).await {
+        Ok(spent) => spent,
+        Err(e) => {
+            auth.release_spend(&state.api_keys, req.amount_msat).await?;
+            return Err(AppError::new(StatusCode::BAD_REQUEST, e));
+        }
+    };
+
+    // The notes may add up to more than requested when no combination matches the amount exactly
+    let surplus = notes
+        .total_amount()
+        .msats
+        .saturating_sub(req.amount_msat.msats);
+    if surplus > 0 {
+        if let Err(e) = auth
+            .record_spend(&state.api_keys, Amount::from_msats(surplus))
+            .await
+        {
+            mint_client.try_cancel_spend_notes(operation_id).await;
+            auth.release_spend(&state.api_keys, req.amount_msat).await?;
+            return Err(e);
+        }
+    }
+
+    Ok(Json(json!({
+        "operation_id": operation_id,
+        "notes": notes,
+    })))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NotesPayload {
+    pub notes: OOBNotes,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_receive(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(req): Json<NotesPayload>,
+) -> Result<Json<Value>, AppError> {
+    let prefix = req.notes.federation_id_prefix();
+    let client = state.multimint.get_by_prefix(&prefix).await.ok_or_else(|| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("No client for the federation the notes belong to: {prefix}"),
+        )
+    })?;
+    let federation_id = client.federation_id();
+    auth.check_federation(&federation_id)?;
+
+    let amount = req.notes.total_amount();
+    let mint_client = first_module::<MintClientModule>(&client)?;
+    let operation_id = mint_client.reissue_external_notes(req.notes, ()).await?;
+    let mut updates = mint_client
+        .subscribe_reissue_external_notes(operation_id)
+        .await?
+        .into_stream();
+
+    while let Some(update) = updates.next().await {
+        match update {
+            ReissueExternalNotesState::Done => {
+                return Ok(Json(json!({
+                    "operation_id": operation_id,
+                    "federation_id": federation_id,
+                    "amount_msat": amount,
+                })));
+            }
+            ReissueExternalNotesState::Failed(e) => {
+                return Err(AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    anyhow!("Failed to reissue notes: {e}"),
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    Err(AppError::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        anyhow!("Reissue update stream ended before the notes were reissued"),
+    ))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_validate(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(req): Json<NotesPayload>,
+) -> Result<Json<Value>, AppError> {
+    let prefix = req.notes.federation_id_prefix();
+    let client = state
+        .multimint
+        .get_by_prefix(&prefix)
+        .await
+        .filter(|client| auth.can_access(&client.federation_id()));
+
+    // Only notes of a federation we have a client for can have their signatures checked
+    let valid = match &client {
+        Some(client) => Some(
+            first_module::<MintClientModule>(client)?
+                .validate_notes(req.notes.clone())
+                .await
+                .is_ok(),
+        ),
+        None => None,
+    };
+
+    Ok(Json(json!({
+        "federation_id_prefix": prefix.to_string(),
+        "federation_id": client.map(|client| client.federation_id()),
+        "amount_msat": req.notes.total_amount(),
+        "denominations_msat": req.notes.notes().summary(),
+        "valid": valid,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::TieredMulti;
+    use multimint::test_utils::federation_id;
+
+    use super::*;
+    use crate::db::Scope;
+    use crate::test_utils::app_state;
+
+    fn notes_of_unknown_federation() -> OOBNotes {
+        OOBNotes::new(federation_id(1).to_prefix(), TieredMulti::default())
+    }
+
+    #[tokio::test]
+    async fn spend_from_an_unknown_federation_records_nothing() {
+        let state = app_state().await;
+        let (id, _, key) = state
+            .api_keys
+            .create(
+                "spender".to_string(),
+                Scope::Spend,
+                None,
+                Some(Amount::from_sats(1_000)),
+            )
+            .await
+            .unwrap();
+
+        let error = handle_spend(
+            State(state.clone()),
+            Extension(AuthContext::ApiKey { id, key }),
+            Json(SpendPayload {
+                federation_id: federation_id(1),
+                amount_msat: Amount::from_sats(100),
+                timeout: None,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status, StatusCode::NOT_FOUND);
+        assert_eq!(state.api_keys.list().await[0].1.spent_msat, Amount::ZERO);
+    }
+
+    #[tokio::test]
+    async fn notes_of_an_unknown_federation_are_not_received() {
+        let error = handle_receive(
+            State(app_state().await),
+            Extension(AuthContext::Master),
+            Json(NotesPayload {
+                notes: notes_of_unknown_federation(),
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn notes_of_an_unknown_federation_cannot_be_validated() {
+        let Json(validation) = handle_validate(
+            State(app_state().await),
+            Extension(AuthContext::Master),
+            Json(NotesPayload {
+                notes: notes_of_unknown_federation(),
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            validation["federation_id_prefix"],
+            federation_id(1).to_prefix().to_string()
+        );
+        assert!(validation["federation_id"].is_null());
+        assert!(validation["valid"].is_null());
+        assert_eq!(validation["amount_msat"], 0);
+    }
+}
@@ -0,0 +1,184 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use fedimint_core::{config::FederationId, Amount};
+use multimint::error::MultiMintError;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{auth::AuthContext, error::AppError, AppState};
+
+/// Parse a federation id from a path segment, answering 404 for ids the multimint has no client for or the caller may not access
+pub async fn known_federation_id(
+    state: &AppState,
+    auth: &AuthContext,
+    federation_id: &str,
+) -> Result<FederationId, AppError> {
+    let federation_id = FederationId::from_str(federation_id).map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Invalid federation id: {e}"),
+        )
+    })?;
+
+    check_known_federation(state, auth, &federation_id).await?;
+    Ok(federation_id)
+}
+
+/// Answer 404 for federations the multimint has no client for or the caller may not access
+pub async fn check_known_federation(
+    state: &AppState,
+    auth: &AuthContext,
+    federation_id: &FederationId,
+) -> Result<(), AppError> {
+    if !state.multimint.has(federation_id).await || !auth.can_access(federation_id) {
+        return Err(AppError::new(
+            StatusCode::NOT_FOUND,
+            MultiMintError::FederationNotFound(*federation_id),
+        ));
+    }
+    Ok(())
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_list_federations(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Json<Value>, AppError> {
+    let federation_ids = state
+        .multimint
+        .ids()
+        .await
+        .into_iter()
+        .filter(|federation_id| auth.can_access(federation_id))
+        .collect::<Vec<_>>();
+
+    Ok(Json(json!(federation_ids)))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_get_federation(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(federation_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let federation_id = known_federation_id(&state, &auth, &federation_id).await?;
+    let info = state.multimint.info_by_id(&federation_id).await?;
+
+    Ok(Json(json!(info)))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_get_federation_config(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(federation_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let federation_id = known_federation_id(&state, &auth, &federation_id).await?;
+    let client = state.multimint.get_or_err(&federation_id).await?;
+
+    Ok(Json(json!(client.get_config_json())))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LeaveFederationQuery {
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_leave_federation(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(federation_id): Path<String>,
+    Query(query): Query<LeaveFederationQuery>,
+) -> Result<Json<Value>, AppError> {
+    let federation_id = known_federation_id(&state, &auth, &federation_id).await?;
+
+    if !query.force {
+        let client = state.multimint.get_or_err(&federation_id).await?;
+        let balance = client.get_balance().await;
+        if balance > Amount::ZERO {
+            return Err(AppError::new(
+                StatusCode::CONFLICT,
+                anyhow!("Federation {federation_id} still holds {balance}, pass force=true to leave anyway"),
+            ));
+        }
+    }
+
+    let final_balance = state.multimint.leave(&federation_id).await?;
+
+    Ok(Json(json!({
+        "federation_id": federation_id,
+        "final_balance_msat": final_balance,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use multimint::test_utils::federation_id;
+
+    use super::*;
+    use crate::test_utils::app_state;
+
+    #[tokio::test]
+    async fn federation_id_must_be_valid_and_known() {
+        let state = app_state().await;
+        let cases = [
+            ("not-a-federation-id".to_string(), StatusCode::BAD_REQUEST),
+            (federation_id(1).to_string(), StatusCode::NOT_FOUND),
+        ];
+
+        for (federation_id, status) in cases {
+            let error = known_federation_id(&state, &AuthContext::Master, &federation_id)
+                .await
+                .unwrap_err();
+            assert_eq!(error.status, status, "{federation_id}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_federation_is_not_found() {
+        let state = app_state().await;
+        let error = handle_get_federation(
+            State(state),
+            Extension(AuthContext::Master),
+            Path(federation_id(1).to_string()),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status, StatusCode::NOT_FOUND);
+        assert_eq!(error.code(), "federation_not_found");
+    }
+
+    #[tokio::test]
+    async fn leaving_an_unknown_federation_is_not_found() {
+        let state = app_state().await;
+        for force in [false, true] {
+            let error = handle_leave_federation(
+                State(state.clone()),
+                Extension(AuthContext::Master),
+                Path(federation_id(1).to_string()),
+                Query(LeaveFederationQuery { force }),
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(error.status, StatusCode::NOT_FOUND, "force: {force}");
+        }
+    }
+
+    #[tokio::test]
+    async fn no_federations_are_listed_before_joining() {
+        let state = app_state().await;
+        let Json(federations) =
+            handle_list_federations(State(state), Extension(AuthContext::Master))
+                .await
+                .unwrap();
+        assert_eq!(federations, json!([]));
+    }
+}
@@ -0,0 +1,15 @@
+use anyhow::Result;
+use axum::{extract::State, Extension, Json};
+use serde_json::{json, Value};
+
+use crate::{auth::AuthContext, error::AppError, AppState};
+
+#[axum_macros::debug_handler]
+pub async fn handle_info(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Json<Value>, AppError> {
+    let mut info = state.multimint.info().await?;
+    info.retain(|federation_id, _| auth.can_access(federation_id));
+    Ok(Json(json!(info)))
+}
@@ -0,0 +1,383 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use fedimint_core::{config::FederationId, core::OperationId, Amount};
+use fedimint_ln_client::{
+    InternalPayState, LightningClientModule, LightningOperationMeta,
+    LightningOperationMetaVariant, LnPayState, OutgoingLightningPayment, PayType,
+};
+use futures_util::StreamExt;
+use lightning_invoice::Bolt11Invoice;
+use multimint::client::first_module;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+
+use super::federations::{check_known_federation, known_federation_id};
+use super::latest_update;
+use crate::{auth::AuthContext, error::AppError, AppState};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoicePayload {
+    pub federation_id: FederationId,
+    pub amount_msat: Amount,
+    pub description: String,
+    /// Seconds until the invoice expires
+    pub expiry_time: Option<u64>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_invoice(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(req): Json<InvoicePayload>,
+) -> Result<Json<Value>, AppError> {
+    check_known_federation(&state, &auth, &req.federation_id).await?;
+    let client = state.multimint.get_or_err(&req.federation_id).await?;
+
+    let lightning_module = first_module::<LightningClientModule>(&client)?;
+    lightning_module.select_active_gateway().await?;
+    let (operation_id, invoice) = lightning_module
+        .create_bolt11_invoice(req.amount_msat, req.description, req.expiry_time, ())
+        .await?;
+
+    Ok(Json(json!({
+        "operation_id": operation_id,
+        "invoice": invoice.to_string(),
+    })))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PayPayload {
+    /// Federation to pay from, the server picks the one with the largest balance covering the invoice if not set
+    pub federation_id: Option<FederationId>,
+    pub invoice: String,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_pay(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(req): Json<PayPayload>,
+) -> Result<Json<Value>, AppError> {
+    let invoice = Bolt11Invoice::from_str(&req.invoice).map_err(|e| {
+        AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid invoice: {e}"))
+    })?;
+    let amount = invoice
+        .amount_milli_satoshis()
+        .map(Amount::from_msats)
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!("Invoices without an amount are not supported"),
+            )
+        })?;
+
+    let federation_id = match req.federation_id {
+        Some(federation_id) => {
+            check_known_federation(&state, &auth, &federation_id).await?;
+            federation_id
+        }
+        None => pick_paying_federation(&state, &auth, amount).await?,
+    };
+    let client = state.multimint.get_or_err(&federation_id).await?;
+
+    // The gateway fee leaves the federation too, so it counts against the spend limit
+    let charged = amount + gateway_fee(&client, amount).await?;
+    auth.record_spend(&state.api_keys, charged).await?;
+
+    match pay_invoice(&client, invoice).await {
+        Ok((operation_id, fee, preimage)) => {
+            // Internal payments don't go through the gateway and pay no fee
+            let overcharged = charged.msats.saturating_sub(amount.msats + fee.msats);
+            if overcharged > 0 {
+                auth.release_spend(&state.api_keys, Amount::from_msats(overcharged))
+                    .await?;
+            }
+
+            Ok(Json(json!({
+                "operation_id": operation_id,
+                "federation_id": federation_id,
+                "fee_msat": fee,
+                "preimage": preimage,
+            })))
+        }
+        Err(e) => {
+            auth.release_spend(&state.api_keys, charged).await?;
+            Err(e)
+        }
+    }
+}
+
+/// The fee the client's gateway charges for paying `amount`
+async fn gateway_fee(
+    client: &fedimint_client::ClientArc,
+    amount: Amount,
+) -> Result<Amount, AppError> {
+    let gateway = first_module::<LightningClientModule>(client)?
+        .select_active_gateway()
+        .await?;
+
+    let proportional =
+        u128::from(amount.msats) * u128::from(gateway.fees.proportional_millionths) / 1_000_000;
+    Ok(Amount::from_msats(
+        u64::from(gateway.fees.base_msat) + proportional as u64,
+    ))
+}
+
+/// The accessible federation with the largest balance that covers `amount`
+async fn pick_paying_federation(
+    state: &AppState,
+    auth: &AuthContext,
+    amount: Amount,
+) -> Result<FederationId, AppError> {
+    state
+        .multimint
+        .ecash_balances()
+        .await?
+        .into_iter()
+        .filter(|(federation_id, balance)| auth.can_access(federation_id) && *balance >= amount)
+        .max_by_key(|(_, balance)| *balance)
+        .map(|(federation_id, _)| federation_id)
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!("No federation has enough ecash to pay {amount}"),
+            )
+        })
+}
+
+/// Pay an invoice and wait for the outcome, returning the operation id, the fee and the preimage
+async fn pay_invoice(
+    client: &fedimint_client::ClientArc,
+    invoice: Bolt11Invoice,
+) -> Result<(OperationId, Amount, String), AppError> {
+    let lightning_module = first_module::<LightningClientModule>(client)?;
+    lightning_module.select_active_gateway().await?;
+
+    let OutgoingLightningPayment {
+        payment_type, fee, ..
+    } = lightning_module
+        .pay_bolt11_invoice(invoice, ())
+        .await
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, e))?;
+
+    match payment_type {
+        PayType::Internal(operation_id) => {
+            let mut updates = lightning_module
+                .subscribe_internal_pay(operation_id)
+                .await?
+                .into_stream();
+
+            while let Some(update) = updates.next().await {
+                if let Some(result) = internal_pay_result(update) {
+                    return result.map(|preimage| (operation_id, fee, preimage));
+                }
+            }
+        }
+        PayType::Lightning(operation_id) => {
+            let mut updates = lightning_module
+                .subscribe_ln_pay(operation_id)
+                .await?
+                .into_stream();
+
+            while let Some(update) = updates.next().await {
+                if let Some(result) = ln_pay_result(update) {
+                    return result.map(|preimage| (operation_id, fee, preimage));
+                }
+            }
+        }
+    }
+
+    Err(AppError::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        anyhow!("Payment update stream ended before the payment completed"),
+    ))
+}
+
+/// The preimage of a payment settled inside the federation, or the error to answer with once it failed. `None` while it is in flight
+fn internal_pay_result(update: InternalPayState) -> Option<Result<String, AppError>> {
+    match update {
+        InternalPayState::Preimage(preimage) => Some(Ok(hex::encode(preimage.0))),
+        InternalPayState::Funding => None,
+        failed => Some(Err(AppError::new(
+            StatusCode::BAD_GATEWAY,
+            anyhow!("Internal payment failed: {failed:?}"),
+        ))),
+    }
+}
+
+/// The preimage of a payment made through a gateway, or the error to answer with once it failed. `None` while it is in flight
+fn ln_pay_result(update: LnPayState) -> Option<Result<String, AppError>> {
+    let error = match update {
+        LnPayState::Success { preimage } => return Some(Ok(preimage)),
+        LnPayState::Refunded { gateway_error } => {
+            anyhow!("Payment failed and was refunded: {gateway_error:?}")
+        }
+        LnPayState::Canceled => anyhow!("Payment was canceled"),
+        LnPayState::UnexpectedError { error_message } => {
+            anyhow!("Payment failed: {error_message}")
+        }
+        _ => return None,
+    };
+    Some(Err(AppError::new(StatusCode::BAD_GATEWAY, error)))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_list_gateways(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(federation_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let federation_id = known_federation_id(&state, &auth, &federation_id).await?;
+    let client = state.multimint.get_or_err(&federation_id).await?;
+
+    let gateways = first_module::<LightningClientModule>(&client)?
+        .fetch_registered_gateways()
+        .await?;
+
+    Ok(Json(json!(gateways)))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_invoice_status(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(operation_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let operation_id = OperationId::from_str(&operation_id).map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Invalid operation id: {e}"),
+        )
+    })?;
+
+    let not_found = || {
+        AppError::new(
+            StatusCode::NOT_FOUND,
+            anyhow!("Unknown invoice operation: {operation_id:?}"),
+        )
+    };
+
+    let (federation_id, _) = state
+        .multimint
+        .find_operation(operation_id)
+        .await
+        .filter(|(federation_id, entry)| {
+            auth.can_access(federation_id)
+                && entry.operation_module_kind() == "ln"
+                && matches!(
+                    entry.meta::<LightningOperationMeta>().variant,
+                    LightningOperationMetaVariant::Receive { .. }
+                )
+        })
+        .ok_or_else(not_found)?;
+
+    let client = state.multimint.get_or_err(&federation_id).await?;
+    let updates = first_module::<LightningClientModule>(&client)?
+        .subscribe_ln_receive(operation_id)
+        .await?
+        .into_stream();
+    let invoice_state = latest_update(updates).await.ok_or_else(|| {
+        AppError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            anyhow!("Invoice update stream ended without any state"),
+        )
+    })?;
+
+    Ok(Json(json!({
+        "operation_id": operation_id,
+        "federation_id": federation_id,
+        "state": invoice_state,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use multimint::test_utils::check_cases;
+
+    use super::*;
+    use crate::test_utils::app_state;
+
+    fn status(result: Option<Result<String, AppError>>) -> Option<Result<String, StatusCode>> {
+        result.map(|result| result.map_err(|e| e.status))
+    }
+
+    #[test]
+    fn gateway_payment_updates_map_to_statuses() {
+        let cases = [
+            (LnPayState::Created, None),
+            (LnPayState::Funded, None),
+            (LnPayState::AwaitingChange, None),
+            (
+                LnPayState::Success {
+                    preimage: "00".repeat(32),
+                },
+                Some(Ok("00".repeat(32))),
+            ),
+            (LnPayState::Canceled, Some(Err(StatusCode::BAD_GATEWAY))),
+            (
+                LnPayState::UnexpectedError {
+                    error_message: "no route".to_string(),
+                },
+                Some(Err(StatusCode::BAD_GATEWAY)),
+            ),
+        ];
+
+        check_cases(cases, |update| status(ln_pay_result(update)));
+    }
+
+    #[test]
+    fn internal_payment_updates_map_to_statuses() {
+        let cases = [
+            (InternalPayState::Funding, None),
+            (
+                InternalPayState::UnexpectedError("no preimage".to_string()),
+                Some(Err(StatusCode::BAD_GATEWAY)),
+            ),
+        ];
+
+        check_cases(cases, |update| status(internal_pay_result(update)));
+    }
+
+    #[tokio::test]
+    async fn invalid_invoice_is_a_bad_request() {
+        let error = handle_pay(
+            State(app_state().await),
+            Extension(AuthContext::Master),
+            Json(PayPayload {
+                federation_id: None,
+                invoice: "not an invoice".to_string(),
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn invoice_status_needs_a_known_operation() {
+        let cases = [
+            ("not an operation id".to_string(), StatusCode::BAD_REQUEST),
+            ("01".repeat(32), StatusCode::NOT_FOUND),
+        ];
+
+        for (operation_id, expected) in cases {
+            let error = handle_invoice_status(
+                State(app_state().await),
+                Extension(AuthContext::Master),
+                Path(operation_id.clone()),
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(error.status, expected, "{operation_id}");
+        }
+    }
+}
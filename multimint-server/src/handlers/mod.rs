@@ -1 +1,26 @@
+pub mod api_keys;
+pub mod balances;
 pub mod connect_federation;
+pub mod ecash;
+pub mod federations;
+pub mod info;
+pub mod ln;
+pub mod onchain;
+pub mod ws;
+
+use std::time::Duration;
+
+use futures_util::{Stream, StreamExt};
+
+/// How long a status request waits for the next update of a running operation before answering with the latest one
+const STATUS_UPDATE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// The latest state of an operation, taken from its update stream: a finished operation yields its outcome right away, a running one every state reached so far
+pub(crate) async fn latest_update<T>(mut updates: impl Stream<Item = T> + Unpin) -> Option<T> {
+    let mut latest = None;
+    while let Ok(Some(update)) = tokio::time::timeout(STATUS_UPDATE_TIMEOUT, updates.next()).await
+    {
+        latest = Some(update);
+    }
+    latest
+}
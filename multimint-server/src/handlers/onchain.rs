@@ -0,0 +1,266 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use bitcoin::Address;
+use fedimint_core::{config::FederationId, core::OperationId, Amount};
+use fedimint_wallet_client::WalletClientModule;
+use multimint::client::first_module;
+use multimint::db::OnchainDirection;
+use multimint::onchain::check_network;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+
+use super::federations::check_known_federation;
+use super::latest_update;
+use crate::{auth::AuthContext, error::AppError, AppState};
+
+/// How long the federation watches a deposit address, if the request doesn't say
+const DEFAULT_DEPOSIT_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DepositAddressPayload {
+    pub federation_id: FederationId,
+    /// Seconds the federation watches the address for a deposit
+    pub timeout: Option<u64>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_deposit_address(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(req): Json<DepositAddressPayload>,
+) -> Result<Json<Value>, AppError> {
+    check_known_federation(&state, &auth, &req.federation_id).await?;
+
+    let timeout = req
+        .timeout
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_DEPOSIT_TIMEOUT);
+    let (operation_id, address) = state
+        .multimint
+        .deposit_address(&req.federation_id, timeout)
+        .await?;
+
+    Ok(Json(json!({
+        "operation_id": operation_id,
+        "address": address.to_string(),
+    })))
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_deposit_status(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Path(operation_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let operation_id = OperationId::from_str(&operation_id).map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Invalid operation id: {e}"),
+        )
+    })?;
+
+    let operation = state
+        .multimint
+        .onchain_operation(operation_id)
+        .await
+        .filter(|operation| {
+            operation.direction == OnchainDirection::Deposit
+                && auth.can_access(&operation.federation_id)
+        })
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::NOT_FOUND,
+                anyhow!("Unknown deposit operation: {operation_id:?}"),
+            )
+        })?;
+
+    let updates = state.multimint.subscribe_deposit(operation_id).await?;
+    let deposit_state = latest_update(updates).await.ok_or_else(|| {
+        AppError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            anyhow!("Deposit update stream ended without any state"),
+        )
+    })?;
+
+    Ok(Json(json!({
+        "operation_id": operation_id,
+        "operation": operation,
+        "state": deposit_state,
+    })))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WithdrawPayload {
+    pub federation_id: FederationId,
+    pub address: String,
+    pub amount_sat: u64,
+    /// Only estimate the fees without withdrawing
+    #[serde(default)]
+    pub preview: bool,
+    /// Refuse to withdraw if the estimated fee is higher
+    pub max_fee_sat: Option<u64>,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_withdraw(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Json(req): Json<WithdrawPayload>,
+) -> Result<Json<Value>, AppError> {
+    check_known_federation(&state, &auth, &req.federation_id).await?;
+    let client = state.multimint.get_or_err(&req.federation_id).await?;
+
+    let address = Address::from_str(&req.address).map_err(|e| {
+        AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid address: {e}"))
+    })?;
+    let network = first_module::<WalletClientModule>(&client)?.get_network();
+    check_network(&address, network).map_err(|e| AppError::new(StatusCode::BAD_REQUEST, e))?;
+
+    let amount = bitcoin::Amount::from_sat(req.amount_sat);
+    let fees = state
+        .multimint
+        .withdraw_fees(&req.federation_id, address.clone(), amount)
+        .await?;
+    let fee_sat = fees.amount().to_sat();
+    let total = Amount::from_sats(req.amount_sat + fee_sat);
+
+    if req.preview {
+        return Ok(Json(json!({
+            "amount_sat": req.amount_sat,
+            "fee_sat": fee_sat,
+            "total_sat": req.amount_sat + fee_sat,
+            "network": network.to_string(),
+        })));
+    }
+
+    if let Some(max_fee_sat) = req.max_fee_sat {
+        if fee_sat > max_fee_sat {
+            return Err(AppError::new(
+                StatusCode::CONFLICT,
+                anyhow!("Estimated fee of {fee_sat} sat exceeds the maximum of {max_fee_sat} sat"),
+            ));
+        }
+    }
+
+    if client.get_balance().await < total {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Not enough ecash to withdraw {total} including fees"),
+        ));
+    }
+
+    auth.record_spend(&state.api_keys, total).await?;
+
+    let operation_id = match state
+        .multimint
+        .withdraw(&req.federation_id, address, amount, fees)
+        .await
+    {
+        Ok(operation_id) => operation_id,
+        Err(e) => {
+            auth.release_spend(&state.api_keys, total).await?;
+            return Err(AppError::new(StatusCode::BAD_REQUEST, e));
+        }
+    };
+
+    let txid = match state.multimint.await_withdraw(operation_id).await {
+        Ok(txid) => txid,
+        Err(e) => {
+            auth.release_spend(&state.api_keys, total).await?;
+            return Err(AppError::new(StatusCode::BAD_GATEWAY, e));
+        }
+    };
+
+    Ok(Json(json!({
+        "operation_id": operation_id,
+        "txid": txid.to_string(),
+        "fee_sat": fee_sat,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use multimint::test_utils::{federation_id, save_onchain_operation};
+
+    use super::*;
+    use crate::db::Scope;
+    use crate::test_utils::app_state;
+
+    #[tokio::test]
+    async fn deposit_status_is_only_answered_for_accessible_deposits() {
+        let state = app_state().await;
+        let withdraw_id = OperationId([1; 32]);
+        let other_deposit_id = OperationId([2; 32]);
+        save_onchain_operation(
+            &state.multimint,
+            withdraw_id,
+            federation_id(1),
+            OnchainDirection::Withdraw,
+        )
+        .await;
+        save_onchain_operation(
+            &state.multimint,
+            other_deposit_id,
+            federation_id(2),
+            OnchainDirection::Deposit,
+        )
+        .await;
+        let (id, _, key) = state
+            .api_keys
+            .create(
+                "reader".to_string(),
+                Scope::ReadOnly,
+                Some(vec![federation_id(1)]),
+                None,
+            )
+            .await
+            .unwrap();
+        let auth = AuthContext::ApiKey { id, key };
+
+        let cases = [
+            ("not an operation id".to_string(), StatusCode::BAD_REQUEST),
+            ("03".repeat(32), StatusCode::NOT_FOUND),
+            (hex::encode(withdraw_id.0), StatusCode::NOT_FOUND),
+            (hex::encode(other_deposit_id.0), StatusCode::NOT_FOUND),
+        ];
+
+        for (operation_id, expected) in cases {
+            let error = handle_deposit_status(
+                State(state.clone()),
+                Extension(auth.clone()),
+                Path(operation_id.clone()),
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(error.status, expected, "{operation_id}");
+        }
+    }
+
+    #[tokio::test]
+    async fn withdraw_from_an_unknown_federation_is_not_found() {
+        let error = handle_withdraw(
+            State(app_state().await),
+            Extension(AuthContext::Master),
+            Json(WithdrawPayload {
+                federation_id: federation_id(1),
+                address: "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq".to_string(),
+                amount_sat: 10_000,
+                preview: true,
+                max_fee_sat: None,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status, StatusCode::NOT_FOUND);
+    }
+}
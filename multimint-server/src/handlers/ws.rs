@@ -0,0 +1,140 @@
+use std::collections::BTreeSet;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    response::Response,
+    Extension,
+};
+use fedimint_core::config::FederationId;
+use futures_util::{SinkExt, StreamExt};
+use multimint::events::MultiMintEvent;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use crate::{auth::AuthContext, AppState};
+
+/// Initial subscription filters, as comma separated lists
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct EventQuery {
+    pub federations: Option<String>,
+    pub types: Option<String>,
+}
+
+/// Which events a socket receives. Clients replace it by sending it as a JSON message. `None` matches everything.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Subscription {
+    pub federations: Option<BTreeSet<FederationId>>,
+    pub types: Option<BTreeSet<String>>,
+}
+
+impl Subscription {
+    fn from_query(query: EventQuery) -> Result<Self, String> {
+        let federations = query
+            .federations
+            .map(|federations| {
+                federations
+                    .split(',')
+                    .map(|federation_id| {
+                        federation_id
+                            .parse::<FederationId>()
+                            .map_err(|e| format!("Invalid federation id {federation_id}: {e}"))
+                    })
+                    .collect::<Result<BTreeSet<_>, _>>()
+            })
+            .transpose()?;
+        let types = query
+            .types
+            .map(|types| types.split(',').map(str::to_string).collect());
+
+        Ok(Self { federations, types })
+    }
+
+    fn matches(&self, event: &MultiMintEvent) -> bool {
+        let federation_matches = self
+            .federations
+            .as_ref()
+            .map_or(true, |federations| federations.contains(&event.federation_id()));
+        let type_matches = self
+            .types
+            .as_ref()
+            .map_or(true, |types| types.contains(event.kind()));
+
+        federation_matches && type_matches
+    }
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_ws(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Query(query): Query<EventQuery>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |socket| stream_events(socket, state, auth, query))
+}
+
+async fn stream_events(socket: WebSocket, state: AppState, auth: AuthContext, query: EventQuery) {
+    let (mut sender, mut receiver) = socket.split();
+    let mut events = state.multimint.subscribe_events();
+
+    let mut subscription = match Subscription::from_query(query) {
+        Ok(subscription) => subscription,
+        Err(e) => {
+            let _ = sender.send(error_message(&e)).await;
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("WebSocket subscriber lagged behind, skipped {skipped} events");
+                        let lagged = json!({ "type": "lagged", "skipped": skipped });
+                        if sender.send(Message::Text(lagged.to_string())).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                if !auth.can_access(&event.federation_id()) || !subscription.matches(&event) {
+                    continue;
+                }
+
+                let Ok(text) = serde_json::to_string(&event) else {
+                    continue;
+                };
+                if sender.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            message = receiver.next() => {
+                match message {
+                    Some(Ok(Message::Text(text))) => match serde_json::from_str::<Subscription>(&text) {
+                        Ok(new_subscription) => subscription = new_subscription,
+                        Err(e) => {
+                            if sender.send(error_message(&format!("Invalid subscription: {e}"))).await.is_err() {
+                                break;
+                            }
+                        }
+                    },
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+}
+
+fn error_message(error: &str) -> Message {
+    Message::Text(json!({ "type": "error", "message": error }).to_string())
+}
@@ -1,46 +1,153 @@
-use axum::{routing::post, Router};
+use axum::{
+    middleware,
+    routing::{delete, get, post},
+    Router,
+};
+
+use std::time::Duration;
 
-// use tower_http::validate_request::ValidateRequestHeaderLayer;
 use anyhow::Result;
-use tracing::info;
+use tracing::{info, warn};
 
-pub mod config;
-pub mod error;
+pub mod api_keys;
+pub mod auth;
+pub mod db;
 pub mod handlers;
 
-use config::CONFIG;
+#[cfg(test)]
+mod test_utils;
+
+pub use multimint_common::error;
 
+use multimint_common::config::Config;
+
+use crate::api_keys::ApiKeyStore;
+use crate::auth::{require_scope, AuthState};
+use crate::db::Scope;
+use crate::handlers::api_keys::{
+    handle_create_api_key, handle_list_api_keys, handle_revoke_api_key,
+};
+use crate::handlers::balances::handle_balances;
 use crate::handlers::connect_federation::handle_connect_federation;
+use crate::handlers::ecash::{handle_receive, handle_spend, handle_validate};
+use crate::handlers::federations::{
+    handle_get_federation, handle_get_federation_config, handle_leave_federation,
+    handle_list_federations,
+};
+use crate::handlers::info::handle_info;
+use crate::handlers::ln::{
+    handle_invoice, handle_invoice_status, handle_list_gateways, handle_pay,
+};
+use crate::handlers::onchain::{
+    handle_deposit_address, handle_deposit_status, handle_withdraw,
+};
+use crate::handlers::ws::handle_ws;
+
+/// How often the operation logs are checked for new operations to report on `/ws`
+const OPERATION_WATCH_INTERVAL: Duration = Duration::from_secs(2);
 
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub multimint: multimint::MultiMint,
+    pub api_keys: ApiKeyStore,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
-    dotenv::dotenv().ok();
+    let config = Config::load()?;
 
-    let multimint = multimint::MultiMint::new(CONFIG.data_dir.clone()).await?;
-    // multimint.register_new(CONFIG.invite_code.clone()).await?;
-    // let new_code = InviteCode::from_str("fed11qgqrgvnhwden5te0v9k8q6rp9ekh2arfdeukuet595cr2ttpd3jhq6rzve6zuer9wchxvetyd938gcewvdhk6tcqqysptkuvknc7erjgf4em3zfh90kffqf9srujn6q53d6r056e4apze5cw27h75").unwrap();
-    // multimint.register_new(new_code).await?;
+    let mut multimint = multimint::MultiMint::new(config.data_dir.clone()).await?;
+    let report = multimint
+        .reconcile(&config.invite_codes, config.leave_unlisted)
+        .await?;
+    if report.failed.is_empty() {
+        info!("Reconciled federations: {report}");
+    } else {
+        warn!("Reconciled federations: {report}");
+    }
+
+    if let Some(interval) = config.meta_refresh_interval() {
+        multimint.spawn_meta_refresh(interval);
+    }
 
     for (federation_id, _federation) in multimint.clients.lock().await.iter() {
         info!("federation_id: {:?}", federation_id);
     }
 
-    let state = AppState { multimint };
-    let app = Router::new()
+    multimint.spawn_operation_watcher(OPERATION_WATCH_INTERVAL);
+
+    let api_keys = ApiKeyStore::new(multimint.db().clone());
+    let auth = AuthState::new(config.password.clone(), api_keys.clone());
+
+    let admin_routes = Router::new()
         .route("/connect_federation", post(handle_connect_federation))
+        .route(
+            "/api_keys",
+            post(handle_create_api_key).get(handle_list_api_keys),
+        )
+        .route("/api_keys/:id", delete(handle_revoke_api_key))
+        .route("/federations/:id", delete(handle_leave_federation))
+        .route_layer(middleware::from_fn_with_state(
+            auth.require(Scope::Admin),
+            require_scope,
+        ));
+
+    let read_routes = Router::new()
+        .route("/federations", get(handle_list_federations))
+        .route("/federations/:id", get(handle_get_federation))
+        .route("/federations/:id/config", get(handle_get_federation_config))
+        .route("/balances", get(handle_balances))
+        .route("/info", get(handle_info))
+        .route("/ecash/validate", post(handle_validate))
+        .route("/ln/gateways/:federation_id", get(handle_list_gateways))
+        .route("/ln/invoice/:operation_id", get(handle_invoice_status))
+        .route("/onchain/deposit/:operation_id", get(handle_deposit_status))
+        .route_layer(middleware::from_fn_with_state(
+            auth.require(Scope::ReadOnly),
+            require_scope,
+        ));
+
+    let ws_routes = Router::new()
+        .route("/ws", get(handle_ws))
+        .route_layer(middleware::from_fn_with_state(
+            auth.require(Scope::ReadOnly).with_query_token(),
+            require_scope,
+        ));
+
+    let receive_routes = Router::new()
+        .route("/ecash/receive", post(handle_receive))
+        .route("/ln/invoice", post(handle_invoice))
+        .route("/onchain/deposit-address", post(handle_deposit_address))
+        .route_layer(middleware::from_fn_with_state(
+            auth.require(Scope::Receive),
+            require_scope,
+        ));
+
+    let spend_routes = Router::new()
+        .route("/ecash/spend", post(handle_spend))
+        .route("/ln/pay", post(handle_pay))
+        .route("/onchain/withdraw", post(handle_withdraw))
+        .route_layer(middleware::from_fn_with_state(
+            auth.require(Scope::Spend),
+            require_scope,
+        ));
+
+    let state = AppState {
+        multimint,
+        api_keys,
+    };
+    let app = Router::new()
+        .merge(read_routes)
+        .merge(ws_routes)
+        .merge(receive_routes)
+        .merge(spend_routes)
+        .merge(admin_routes)
         .with_state(state);
 
-    let listener = tokio::net::TcpListener::bind(format!("{}:{}", CONFIG.host, CONFIG.port))
-        .await
-        .unwrap();
+    let listener = tokio::net::TcpListener::bind(config.bind_address()).await?;
 
-    info!("Listening on {}", CONFIG.port);
+    info!("Listening on {}", config.port);
 
     axum::serve(listener, app).await.unwrap();
 
@@ -0,0 +1,18 @@
+//! Fixtures shared by the tests of the server
+
+use fedimint_core::db::{mem_impl::MemDatabase, Database};
+
+use crate::api_keys::ApiKeyStore;
+use crate::AppState;
+
+pub fn api_keys() -> ApiKeyStore {
+    ApiKeyStore::new(Database::new(MemDatabase::new(), Default::default()))
+}
+
+/// A server state without any federation
+pub async fn app_state() -> AppState {
+    AppState {
+        multimint: multimint::test_utils::multimint().await,
+        api_keys: api_keys(),
+    }
+}
@@ -1,48 +1,350 @@
-use fedimint_core::api::InviteCode;
-use std::env;
-use std::path::PathBuf;
-use std::str::FromStr;
-use tracing::info;
+//! Swap settings, read from the `[swap]` section of the config file
+//!
+//! Example section:
+//!
+//! ```toml
+//! [swap]
+//! quote_ttl_secs = 60
+//! default_fee = { base_msat = 1000, ppm = 5000 }
+//! lightning_fee = { base_msat = 2000, ppm = 5000 }
+//! only_listed_pairs = true
+//! min_amount_msat = 10000
+//! max_amount_msat = 100000000
+//! default_reserve = { min_msat = 50000000 }
+//!
+//! [[swap.pairs]]
+//! from_federation_id = "15db8cb4f1ec8e484d73b889372bec94812580f929e8148b7437d359af422cd3"
+//! to_federation_id = "0a3ffd3e8eb9bb9d9b5d5bae21a7d5ee5a4a7e2c9bde1bf1a1b2a3a8f8d1b0c7"
+//! fee = { base_msat = 0, ppm = 2000 }
+//! max_amount_msat = 500000000
+//!
+//! [[swap.federations]]
+//! federation_id = "15db8cb4f1ec8e484d73b889372bec94812580f929e8148b7437d359af422cd3"
+//! min_msat = 100000000
+//! max_msat = 2000000000
+//!
+//! [swap.rebalance]
+//! enabled = true
+//! dry_run = true
+//! max_fee_ppm = 3000
+//! daily_fee_budget_msat = 50000
+//! ```
 
-lazy_static::lazy_static! {
-    pub static ref CONFIG: Config =
-        Config::from_env().expect("Failed to load config from environment");
+use fedimint_core::config::FederationId;
+use fedimint_core::Amount;
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_QUOTE_TTL_SECS: u64 = 60;
+const DEFAULT_REBALANCE_INTERVAL_SECS: u64 = 300;
+const DEFAULT_REBALANCE_MAX_FEE_PPM: u64 = 5000;
+
+/// A fee of `base_msat` plus `ppm` parts per million of the swapped amount
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fee {
+    #[serde(default)]
+    pub base_msat: u64,
+    #[serde(default)]
+    pub ppm: u64,
 }
 
-pub struct Config {
-    pub data_dir: PathBuf,
-    pub invite_code: InviteCode,
-    pub host: String,
-    pub port: u16,
-    pub password: String,
+impl Fee {
+    pub fn amount(&self, amount: Amount) -> Amount {
+        let proportional = (u128::from(amount.msats) * u128::from(self.ppm) / 1_000_000) as u64;
+        Amount::from_msats(self.base_msat.saturating_add(proportional))
+    }
 }
 
-impl Config {
-    pub fn from_env() -> Result<Self, env::VarError> {
-        dotenv::dotenv().ok();
+/// Fee and amount limits of swaps from one federation to another, overriding the defaults
+#[derive(Debug, Clone, Deserialize)]
+pub struct PairConfig {
+    pub from_federation_id: FederationId,
+    pub to_federation_id: FederationId,
+    pub fee: Option<Fee>,
+    pub min_amount_msat: Option<u64>,
+    pub max_amount_msat: Option<u64>,
+}
 
-        let data_dir = env::var("DATA_DIR").expect("DATA_DIR must be set");
-        let data_dir = PathBuf::from_str(&data_dir).expect("Invalid fm db path");
+/// Bounds of the ecash balance the server keeps in a federation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct Reserve {
+    /// Swaps that would take the balance below this are rejected
+    #[serde(default)]
+    pub min_msat: u64,
+    /// A balance above this is reported as over-funded
+    pub max_msat: Option<u64>,
+    /// The balance the rebalancer aims for, by default halfway between the bounds, or the minimum without a maximum
+    pub target_msat: Option<u64>,
+}
 
-        let invite_code =
-            InviteCode::from_str(&env::var("INVITE_CODE").expect("INVITE_CODE must be set"))
-                .expect("Invalid invite code");
+impl Reserve {
+    pub fn target_msat(&self) -> u64 {
+        self.target_msat.unwrap_or(match self.max_msat {
+            Some(max_msat) => self.min_msat + max_msat.saturating_sub(self.min_msat) / 2,
+            None => self.min_msat,
+        })
+    }
+}
 
-        let host = env::var("HOST").expect("HOST must be set");
+#[derive(Debug, Clone, Deserialize)]
+pub struct FederationConfig {
+    pub federation_id: FederationId,
+    #[serde(flatten)]
+    pub reserve: Reserve,
+}
 
-        let port =
-            u16::from_str(&env::var("PORT").unwrap_or("3000".to_string())).expect("Invalid port");
+/// Settings of the background rebalancer moving funds between federations over Lightning
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RebalanceConfig {
+    pub enabled: bool,
+    /// Log the rebalances that would be made without moving any funds
+    pub dry_run: bool,
+    pub interval_secs: u64,
+    /// Largest amount moved by a single rebalance
+    pub max_amount_msat: Option<u64>,
+    /// Rebalances whose estimated gateway fee is above this share of the amount are skipped
+    pub max_fee_ppm: u64,
+    /// Total Lightning fees the rebalancer may spend over the last 24 hours
+    pub daily_fee_budget_msat: Option<u64>,
+}
 
-        let password = env::var("PASSWORD").expect("PASSWORD must be set");
+impl Default for RebalanceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dry_run: false,
+            interval_secs: DEFAULT_REBALANCE_INTERVAL_SECS,
+            max_amount_msat: None,
+            max_fee_ppm: DEFAULT_REBALANCE_MAX_FEE_PPM,
+            daily_fee_budget_msat: None,
+        }
+    }
+}
 
-        info!("Loaded config");
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SwapConfig {
+    /// How long a quote can be used for a swap
+    pub quote_ttl_secs: u64,
+    /// Key quotes are signed with, derived from the server password when unset
+    pub quote_secret: Option<String>,
+    /// Fee for the pairs without their own
+    pub default_fee: Fee,
+    /// Fee of swaps to or from Lightning, on top of the gateway fee when the server pays an invoice
+    pub lightning_fee: Fee,
+    /// Smallest amount of ecash accepted in a swap, for the pairs without their own
+    pub min_amount_msat: u64,
+    /// Largest amount of ecash accepted in a swap, for the pairs without their own
+    pub max_amount_msat: Option<u64>,
+    pub pairs: Vec<PairConfig>,
+    /// Only swap between the pairs listed in `pairs`, instead of between any two joined federations. Lightning swaps are then only made with the federations of a listed pair
+    pub only_listed_pairs: bool,
+    /// Reserve of the federations without their own
+    pub default_reserve: Reserve,
+    pub federations: Vec<FederationConfig>,
+    pub rebalance: RebalanceConfig,
+}
 
-        Ok(Self {
-            data_dir,
-            invite_code,
-            host,
-            port,
-            password,
-        })
+impl Default for SwapConfig {
+    fn default() -> Self {
+        Self {
+            quote_ttl_secs: DEFAULT_QUOTE_TTL_SECS,
+            quote_secret: None,
+            default_fee: Fee::default(),
+            lightning_fee: Fee::default(),
+            min_amount_msat: 0,
+            max_amount_msat: None,
+            pairs: Vec::new(),
+            only_listed_pairs: false,
+            default_reserve: Reserve::default(),
+            federations: Vec::new(),
+            rebalance: RebalanceConfig::default(),
+        }
+    }
+}
+
+impl SwapConfig {
+    fn pair(&self, from: FederationId, to: FederationId) -> Option<&PairConfig> {
+        self.pairs
+            .iter()
+            .find(|pair| pair.from_federation_id == from && pair.to_federation_id == to)
+    }
+
+    pub fn is_supported(&self, from: FederationId, to: FederationId) -> bool {
+        from != to && (!self.only_listed_pairs || self.pair(from, to).is_some())
+    }
+
+    /// Whether Lightning payments are swapped to and from the federation's ecash
+    pub fn is_lightning_supported(&self, federation_id: FederationId) -> bool {
+        !self.only_listed_pairs
+            || self.pairs.iter().any(|pair| {
+                pair.from_federation_id == federation_id || pair.to_federation_id == federation_id
+            })
+    }
+
+    pub fn fee(&self, from: FederationId, to: FederationId) -> Fee {
+        self.pair(from, to)
+            .and_then(|pair| pair.fee)
+            .unwrap_or(self.default_fee)
+    }
+
+    /// The smallest and the largest amount of ecash accepted in a swap between the federations
+    pub fn amount_limits(&self, from: FederationId, to: FederationId) -> (Amount, Option<Amount>) {
+        let pair = self.pair(from, to);
+        let min = pair
+            .and_then(|pair| pair.min_amount_msat)
+            .unwrap_or(self.min_amount_msat);
+        let max = pair
+            .and_then(|pair| pair.max_amount_msat)
+            .or(self.max_amount_msat);
+        (Amount::from_msats(min), max.map(Amount::from_msats))
+    }
+
+    pub fn reserve(&self, federation_id: FederationId) -> Reserve {
+        self.federations
+            .iter()
+            .find(|federation| federation.federation_id == federation_id)
+            .map(|federation| federation.reserve)
+            .unwrap_or(self.default_reserve)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use multimint::test_utils::{check_cases, federation_id};
+
+    use super::*;
+
+    const PAIR_FEE: Fee = Fee {
+        base_msat: 0,
+        ppm: 2_000,
+    };
+    const DEFAULT_FEE: Fee = Fee {
+        base_msat: 1_000,
+        ppm: 5_000,
+    };
+
+    /// Federations 1 and 2 form a listed pair from 1 to 2 with its own fee and maximum, 3 is only covered by the defaults
+    fn config(only_listed_pairs: bool) -> SwapConfig {
+        SwapConfig {
+            default_fee: DEFAULT_FEE,
+            min_amount_msat: 10_000,
+            max_amount_msat: Some(100_000_000),
+            only_listed_pairs,
+            pairs: vec![PairConfig {
+                from_federation_id: federation_id(1),
+                to_federation_id: federation_id(2),
+                fee: Some(PAIR_FEE),
+                min_amount_msat: None,
+                max_amount_msat: Some(500_000_000),
+            }],
+            ..SwapConfig::default()
+        }
+    }
+
+    #[test]
+    fn supported_pairs() {
+        let cases = [
+            ((false, 1, 2), true),
+            ((false, 2, 1), true),
+            ((false, 1, 3), true),
+            ((false, 1, 1), false),
+            ((true, 1, 2), true),
+            ((true, 2, 1), false),
+            ((true, 1, 3), false),
+            ((true, 2, 2), false),
+        ];
+
+        check_cases(cases, |(only_listed_pairs, from, to)| {
+            config(only_listed_pairs).is_supported(federation_id(from), federation_id(to))
+        });
+    }
+
+    #[test]
+    fn lightning_supported_federations() {
+        let cases = [
+            ((false, 1), true),
+            ((false, 3), true),
+            ((true, 1), true),
+            ((true, 2), true),
+            ((true, 3), false),
+        ];
+
+        check_cases(cases, |(only_listed_pairs, federation)| {
+            config(only_listed_pairs).is_lightning_supported(federation_id(federation))
+        });
+    }
+
+    #[test]
+    fn pair_fee_overrides_default() {
+        let config = config(false);
+        let cases = [
+            ((1, 2), PAIR_FEE),
+            ((2, 1), DEFAULT_FEE),
+            ((1, 3), DEFAULT_FEE),
+        ];
+
+        check_cases(cases, |(from, to)| {
+            config.fee(federation_id(from), federation_id(to))
+        });
+    }
+
+    #[test]
+    fn fee_amount() {
+        let cases = [
+            ((Fee::default(), 1_000_000), 0),
+            ((DEFAULT_FEE, 0), 1_000),
+            ((DEFAULT_FEE, 1_000_000), 6_000),
+            ((PAIR_FEE, 999), 1),
+            ((PAIR_FEE, 499), 0),
+        ];
+
+        check_cases(cases, |(fee, amount_msat)| {
+            fee.amount(Amount::from_msats(amount_msat)).msats
+        });
+    }
+
+    #[test]
+    fn amount_limits_fall_back_to_defaults() {
+        let config = config(false);
+        let cases = [
+            ((1, 2), (10_000, Some(500_000_000))),
+            ((2, 1), (10_000, Some(100_000_000))),
+            ((1, 3), (10_000, Some(100_000_000))),
+        ];
+
+        check_cases(cases, |(from, to)| {
+            let (min, max) = config.amount_limits(federation_id(from), federation_id(to));
+            (min.msats, max.map(|max| max.msats))
+        });
+    }
+
+    #[test]
+    fn amount_limits_without_maximum() {
+        let config = SwapConfig::default();
+        assert_eq!(
+            config.amount_limits(federation_id(1), federation_id(2)),
+            (Amount::ZERO, None)
+        );
+    }
+
+    #[test]
+    fn reserve_target() {
+        let cases = [
+            ((0, None, None), 0),
+            ((1_000, None, None), 1_000),
+            ((1_000, Some(3_000), None), 2_000),
+            ((1_000, Some(3_000), Some(2_500)), 2_500),
+            ((3_000, Some(1_000), None), 3_000),
+        ];
+
+        check_cases(cases, |(min_msat, max_msat, target_msat)| {
+            Reserve {
+                min_msat,
+                max_msat,
+                target_msat,
+            }
+            .target_msat()
+        });
     }
 }
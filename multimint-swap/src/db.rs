@@ -0,0 +1,175 @@
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount};
+use fedimint_mint_client::OOBNotes;
+use multimint::db::TransferState;
+use serde::{Deserialize, Serialize};
+
+/// Key prefixes of the swap server's records in the multimint database, starting at the range `multimint` leaves to applications
+#[repr(u8)]
+#[derive(Clone, Debug)]
+pub enum DbKeyPrefix {
+    Swap = 0x30,
+    IdempotencyKey = 0x31,
+    Rebalance = 0x32,
+}
+
+impl std::fmt::Display for DbKeyPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct SwapKey {
+    pub id: OperationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct SwapKeyPrefix;
+
+/// What the user sends and gets back in a swap
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SwapMode {
+    /// Ecash of one federation for ecash of another
+    Ecash,
+    /// A Lightning payment to the server for ecash of a federation
+    LnToEcash,
+    /// Ecash of a federation for the payment of a Lightning invoice
+    EcashToLn,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "state")]
+pub enum SwapState {
+    /// The incoming ecash is being reissued on its own federation
+    Pending,
+    /// The server is waiting for the user to pay `invoice`, received by the Lightning operation `operation_id`
+    AwaitingPayment {
+        invoice: String,
+        operation_id: OperationId,
+    },
+    /// The incoming ecash or Lightning payment was received, the outgoing ecash or payment is not made yet
+    Reissued,
+    /// The outgoing ecash or payment is being made. `operation_id` is the payment of the user's invoice in an `EcashToLn` swap, recorded before paying so an interrupted payment is awaited rather than made again
+    PayingOut { operation_id: Option<OperationId> },
+    /// The swap is done, `notes` is the ecash handed to the user
+    Completed { notes: OOBNotes },
+    /// The user's invoice was paid
+    Paid { preimage: String },
+    /// The outgoing ecash could not be created, `notes` is the incoming amount handed back on the source federation
+    Refunded { notes: OOBNotes, error: String },
+    /// Neither the outgoing ecash nor a refund could be created. The user can claim what they are owed with the swap id
+    Owed { error: String },
+    /// The incoming ecash was not accepted, the user keeps it
+    Failed { error: String },
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct SwapRecord {
+    pub mode: SwapMode,
+    /// The federation the incoming ecash or Lightning payment is received in
+    pub from_federation_id: FederationId,
+    /// The federation the outgoing ecash or Lightning payment is made from
+    pub to_federation_id: FederationId,
+    pub amount_in: Amount,
+    pub amount_out: Amount,
+    pub state: SwapState,
+    /// The invoice the server pays in an `EcashToLn` swap
+    pub invoice: Option<String>,
+    pub idempotency_key: Option<String>,
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+    /// Unix timestamp in seconds
+    pub updated_at: u64,
+}
+
+impl_db_record!(
+    key = SwapKey,
+    value = SwapRecord,
+    db_prefix = DbKeyPrefix::Swap,
+);
+
+impl_db_lookup!(key = SwapKey, query_prefix = SwapKeyPrefix);
+
+/// Maps a client-supplied idempotency key to the swap it started
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct IdempotencyKey {
+    pub key: String,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct IdempotencyKeyPrefix;
+
+impl_db_record!(
+    key = IdempotencyKey,
+    value = SwapKey,
+    db_prefix = DbKeyPrefix::IdempotencyKey,
+);
+
+impl_db_lookup!(key = IdempotencyKey, query_prefix = IdempotencyKeyPrefix);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct RebalanceKey {
+    pub id: OperationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct RebalanceKeyPrefix;
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "outcome")]
+pub enum RebalanceOutcome {
+    /// The rebalancer ran in dry-run mode and moved nothing
+    DryRun,
+    /// A multimint transfer was made, `state` is its final state
+    Transferred {
+        transfer_id: OperationId,
+        state: TransferState,
+        fee: Amount,
+    },
+    /// The transfer could not be started
+    Failed { error: String },
+}
+
+/// A move of funds from an over-funded to an under-funded federation
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct RebalanceRecord {
+    pub from_federation_id: FederationId,
+    pub to_federation_id: FederationId,
+    pub amount: Amount,
+    /// Gateway fee expected before the transfer was made
+    pub estimated_fee: Amount,
+    pub outcome: RebalanceOutcome,
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+}
+
+impl RebalanceRecord {
+    /// The Lightning fee actually paid
+    pub fn fee(&self) -> Amount {
+        match &self.outcome {
+            RebalanceOutcome::Transferred { fee, .. } => *fee,
+            RebalanceOutcome::DryRun | RebalanceOutcome::Failed { .. } => Amount::ZERO,
+        }
+    }
+
+    /// Whether the rebalance tried to move funds and did not
+    pub fn is_failure(&self) -> bool {
+        match &self.outcome {
+            RebalanceOutcome::Transferred { state, .. } => *state != TransferState::Succeeded,
+            RebalanceOutcome::Failed { .. } => true,
+            RebalanceOutcome::DryRun => false,
+        }
+    }
+}
+
+impl_db_record!(
+    key = RebalanceKey,
+    value = RebalanceRecord,
+    db_prefix = DbKeyPrefix::Rebalance,
+);
+
+impl_db_lookup!(key = RebalanceKey, query_prefix = RebalanceKeyPrefix);
@@ -1,10 +1,76 @@
-use crate::{error::AppError, AppState};
 use anyhow::Result;
 use axum::{extract::State, Json};
+use fedimint_core::{config::FederationId, Amount};
+use serde::Serialize;
 use serde_json::{json, Value};
 
+use crate::{config::Fee, error::AppError, liquidity::liquidity_bucket, AppState};
+
+#[derive(Debug, Serialize)]
+pub struct FederationInfo {
+    pub federation_id: FederationId,
+    pub name: Option<String>,
+    /// Ecash of the federation the server can pay out, rounded down to a power of ten sats
+    pub liquidity: Amount,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PairInfo {
+    pub from_federation_id: FederationId,
+    pub to_federation_id: FederationId,
+    pub fee: Fee,
+    pub min_amount: Amount,
+    pub max_amount: Option<Amount>,
+}
+
+/// What users need to pick a swap: the federations and pairs the server swaps between, their fees and limits, and a rough idea of the available liquidity
 #[axum_macros::debug_handler]
 pub async fn handle_info(State(state): State<AppState>) -> Result<Json<Value>, AppError> {
+    let config = state.quoter.config();
+    let statuses = state.liquidity.statuses().await;
+
+    let mut federations = Vec::new();
+    for status in &statuses {
+        let name = state
+            .multimint
+            .federation_meta(&status.federation_id)
+            .await
+            .and_then(|meta| meta.name().map(str::to_string));
+        federations.push(FederationInfo {
+            federation_id: status.federation_id,
+            name,
+            liquidity: liquidity_bucket(status.available()),
+        });
+    }
+
+    let pairs = statuses
+        .iter()
+        .flat_map(|from| statuses.iter().map(move |to| (from, to)))
+        .filter(|(from, to)| config.is_supported(from.federation_id, to.federation_id))
+        .map(|(from, to)| {
+            let (min_amount, max_amount) =
+                config.amount_limits(from.federation_id, to.federation_id);
+            PairInfo {
+                from_federation_id: from.federation_id,
+                to_federation_id: to.federation_id,
+                fee: config.fee(from.federation_id, to.federation_id),
+                min_amount,
+                max_amount,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Json(json!({
+        "federations": federations,
+        "pairs": pairs,
+        "lightning_fee": config.lightning_fee,
+        "quote_ttl_secs": config.quote_ttl_secs,
+    })))
+}
+
+/// Everything the multimint knows about the joined federations, including the exact balances and note denominations
+#[axum_macros::debug_handler]
+pub async fn handle_admin_info(State(state): State<AppState>) -> Result<Json<Value>, AppError> {
     let info = state.multimint.info().await?;
     Ok(Json(json!(info)))
 }
@@ -0,0 +1,239 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use axum::{extract::State, http::StatusCode, Json};
+use fedimint_core::{config::FederationId, core::OperationId, Amount};
+use lightning_invoice::Bolt11Invoice;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use super::swap::{get_clients, pay_out, replay_swap, swap_ecash, swap_response, SwapPayload};
+use crate::{
+    db::{SwapMode, SwapRecord, SwapState},
+    error::AppError,
+    lightning::{await_receive, create_invoice, gateway_fee},
+    quotes::SignedQuote,
+    swaps::unix_now,
+    AppState,
+};
+
+/// How long the user has to pay the invoice of a `ln_to_ecash` swap
+const INVOICE_EXPIRY_SECS: u64 = 3600;
+
+#[derive(Debug, Deserialize)]
+pub struct LnToEcashQuotePayload {
+    /// The federation whose ecash the user gets
+    pub federation_id: FederationId,
+    /// The amount the user will pay over Lightning
+    pub amount_msat: u64,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_quote_ln_to_ecash(
+    State(state): State<AppState>,
+    Json(req): Json<LnToEcashQuotePayload>,
+) -> Result<Json<Value>, AppError> {
+    state.multimint.get_or_err(&req.federation_id).await?;
+
+    let quote = state
+        .quoter
+        .quote_ln_to_ecash(req.federation_id, Amount::from_msats(req.amount_msat))
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, e))?;
+    check_quote(&state, &quote).await?;
+
+    Ok(Json(json!(quote)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EcashToLnQuotePayload {
+    /// The federation whose ecash the user sends
+    pub federation_id: FederationId,
+    /// The invoice the server pays
+    pub invoice: String,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_quote_ecash_to_ln(
+    State(state): State<AppState>,
+    Json(req): Json<EcashToLnQuotePayload>,
+) -> Result<Json<Value>, AppError> {
+    let client = state.multimint.get_or_err(&req.federation_id).await?;
+
+    let invoice = Bolt11Invoice::from_str(&req.invoice).map_err(|e| {
+        AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid invoice: {e}"))
+    })?;
+    if invoice.is_expired() {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("The invoice expired"),
+        ));
+    }
+    let amount = invoice
+        .amount_milli_satoshis()
+        .map(Amount::from_msats)
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!("Invoices without an amount are not supported"),
+            )
+        })?;
+    let gateway_fee = gateway_fee(&client, amount)
+        .await
+        .map_err(|e| AppError::new(StatusCode::SERVICE_UNAVAILABLE, e))?;
+
+    let quote = state
+        .quoter
+        .quote_ecash_to_ln(req.federation_id, req.invoice, amount, gateway_fee)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, e))?;
+    check_quote(&state, &quote).await?;
+
+    Ok(Json(json!(quote)))
+}
+
+async fn check_quote(state: &AppState, quote: &SignedQuote) -> Result<(), AppError> {
+    state
+        .liquidity
+        .check_swap(
+            quote.quote.from_federation_id,
+            quote.quote.to_federation_id,
+            quote.quote.amount_in,
+            quote.quote.amount_out,
+        )
+        .await
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LnToEcashPayload {
+    /// A quote from `POST /quote/ln-to-ecash`
+    pub quote: SignedQuote,
+    /// Lets a client retry a swap whose response it lost without swapping twice
+    pub idempotency_key: Option<String>,
+}
+
+/// Start a swap of a Lightning payment for ecash, answering with the invoice to pay.
+///
+/// The ecash is created once the invoice is paid and can be fetched with `GET /swap/:id`.
+#[axum_macros::debug_handler]
+pub async fn handle_swap_ln_to_ecash(
+    State(state): State<AppState>,
+    Json(req): Json<LnToEcashPayload>,
+) -> Result<Json<Value>, AppError> {
+    if let Some(key) = &req.idempotency_key {
+        if let Some((swap_id, record)) = state.swaps.get_by_idempotency_key(key).await {
+            return replay_swap(swap_id, record, &req.quote);
+        }
+    }
+
+    state
+        .quoter
+        .verify(&req.quote, SwapMode::LnToEcash)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, e))?;
+    let quote = &req.quote.quote;
+    check_quote(&state, &req.quote).await?;
+
+    let client = state.multimint.get_or_err(&quote.from_federation_id).await?;
+    let (operation_id, invoice) = create_invoice(
+        &client,
+        quote.amount_in,
+        "multimint swap".to_string(),
+        INVOICE_EXPIRY_SECS,
+    )
+    .await
+    .map_err(|e| AppError::new(StatusCode::SERVICE_UNAVAILABLE, e))?;
+
+    let now = unix_now();
+    let record = SwapRecord {
+        mode: SwapMode::LnToEcash,
+        from_federation_id: quote.from_federation_id,
+        to_federation_id: quote.to_federation_id,
+        amount_in: quote.amount_in,
+        amount_out: quote.amount_out,
+        state: SwapState::AwaitingPayment {
+            invoice: invoice.to_string(),
+            operation_id,
+        },
+        invoice: None,
+        idempotency_key: req.idempotency_key.clone(),
+        created_at: now,
+        updated_at: now,
+    };
+    let swap_id = quote.id;
+    state
+        .swaps
+        .create(swap_id, &record)
+        .await
+        .map_err(|e| AppError::new(StatusCode::CONFLICT, e).with_code("quote_already_used"))?;
+
+    spawn_payment_watcher(state, swap_id, record.clone(), operation_id);
+
+    swap_response(swap_id, record)
+}
+
+/// Pay the user's invoice out of the ecash they send
+#[axum_macros::debug_handler]
+pub async fn handle_swap_ecash_to_ln(
+    State(state): State<AppState>,
+    Json(req): Json<SwapPayload>,
+) -> Result<Json<Value>, AppError> {
+    swap_ecash(state, req, SwapMode::EcashToLn).await
+}
+
+/// Wait in the background for the invoice of a `ln_to_ecash` swap to be paid, then create its ecash
+fn spawn_payment_watcher(
+    state: AppState,
+    swap_id: OperationId,
+    record: SwapRecord,
+    operation_id: OperationId,
+) {
+    tokio::spawn(async move {
+        if let Err(e) = watch_payment(&state, swap_id, &record, operation_id).await {
+            warn!("Swap {swap_id:?} failed: {:?}", e.error);
+        }
+    });
+}
+
+async fn watch_payment(
+    state: &AppState,
+    swap_id: OperationId,
+    record: &SwapRecord,
+    operation_id: OperationId,
+) -> Result<(), AppError> {
+    let (from_client, to_client) = get_clients(
+        &state.multimint,
+        &record.from_federation_id,
+        &record.to_federation_id,
+    )
+    .await?;
+
+    if let Err(e) = await_receive(&from_client, operation_id).await {
+        state
+            .swaps
+            .update_state(
+                swap_id,
+                SwapState::Failed {
+                    error: e.to_string(),
+                },
+            )
+            .await?;
+        return Ok(());
+    }
+
+    info!("Swap {swap_id:?} received its Lightning payment");
+    let record = state
+        .swaps
+        .update_state(swap_id, SwapState::Reissued)
+        .await?;
+    pay_out(&state.swaps, swap_id, &record, &from_client, &to_client).await?;
+
+    Ok(())
+}
+
+/// Resume waiting for the payments of the `ln_to_ecash` swaps started before the server restarted
+pub async fn resume_payment_watchers(state: &AppState) {
+    for (swap_id, record) in state.swaps.list().await {
+        if let SwapState::AwaitingPayment { operation_id, .. } = record.state {
+            spawn_payment_watcher(state.clone(), swap_id, record, operation_id);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use crate::{error::AppError, AppState};
+use anyhow::Result;
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+    Json,
+};
+use serde_json::{json, Value};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+#[axum_macros::debug_handler]
+pub async fn handle_liquidity(State(state): State<AppState>) -> Result<Json<Value>, AppError> {
+    let statuses = state.liquidity.statuses().await;
+    Ok(Json(json!({ "federations": statuses })))
+}
+
+/// Stream a `LiquidityAlert` to the socket whenever a federation's reserve level changes
+#[axum_macros::debug_handler]
+pub async fn handle_liquidity_events(
+    State(state): State<AppState>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |socket| stream_alerts(socket, state))
+}
+
+async fn stream_alerts(mut socket: WebSocket, state: AppState) {
+    let mut alerts = state.liquidity.subscribe_alerts();
+
+    loop {
+        let alert = match alerts.recv().await {
+            Ok(alert) => alert,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Liquidity alert subscriber lagged behind, skipped {skipped} alerts");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        let Ok(text) = serde_json::to_string(&alert) else {
+            continue;
+        };
+        if socket.send(Message::Text(text)).await.is_err() {
+            break;
+        }
+    }
+}
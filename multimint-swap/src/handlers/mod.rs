@@ -1,2 +1,6 @@
 pub mod info;
+pub mod lightning;
+pub mod liquidity;
+pub mod quote;
+pub mod rebalance;
 pub mod swap;
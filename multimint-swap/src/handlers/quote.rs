@@ -0,0 +1,44 @@
+use crate::{error::AppError, AppState};
+use anyhow::Result;
+use axum::{extract::State, http::StatusCode, Json};
+use fedimint_core::{config::FederationId, Amount};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+#[derive(Debug, Deserialize)]
+pub struct QuotePayload {
+    pub from_federation_id: FederationId,
+    pub to_federation_id: FederationId,
+    /// The amount of ecash the user will send
+    pub amount_msat: u64,
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_quote(
+    State(state): State<AppState>,
+    Json(req): Json<QuotePayload>,
+) -> Result<Json<Value>, AppError> {
+    for federation_id in [req.from_federation_id, req.to_federation_id] {
+        state.multimint.get_or_err(&federation_id).await?;
+    }
+
+    let quote = state
+        .quoter
+        .quote(
+            req.from_federation_id,
+            req.to_federation_id,
+            Amount::from_msats(req.amount_msat),
+        )
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, e))?;
+    state
+        .liquidity
+        .check_swap(
+            quote.quote.from_federation_id,
+            quote.quote.to_federation_id,
+            quote.quote.amount_in,
+            quote.quote.amount_out,
+        )
+        .await?;
+
+    Ok(Json(json!(quote)))
+}
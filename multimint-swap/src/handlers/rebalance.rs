@@ -0,0 +1,16 @@
+use crate::{error::AppError, AppState};
+use anyhow::Result;
+use axum::{extract::State, Json};
+use serde_json::{json, Value};
+
+#[axum_macros::debug_handler]
+pub async fn handle_rebalances(State(state): State<AppState>) -> Result<Json<Value>, AppError> {
+    let mut rebalances = state.rebalancer.rebalances().await;
+    rebalances.sort_by_key(|(_, record)| std::cmp::Reverse(record.created_at));
+
+    let rebalances = rebalances
+        .into_iter()
+        .map(|(id, record)| json!({ "id": id, "rebalance": record }))
+        .collect::<Vec<_>>();
+    Ok(Json(json!({ "rebalances": rebalances })))
+}
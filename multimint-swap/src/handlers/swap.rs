@@ -1,26 +1,79 @@
-use std::{collections::BTreeMap, time::Duration};
+use std::str::FromStr;
+use std::time::Duration;
 
-use crate::{error::AppError, AppState};
+use crate::{
+    db::{SwapMode, SwapRecord, SwapState},
+    error::AppError,
+    lightning::{await_payment, pay_invoice, payment_operation_id},
+    quotes::{Quote, SignedQuote},
+    swaps::{unix_now, SwapStore},
+    AppState,
+};
 use anyhow::{anyhow, Result};
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
 use fedimint_client::ClientArc;
-use fedimint_core::{config::FederationId, Amount};
-use fedimint_mint_client::{MintClientModule, OOBNotes, SelectNotesWithAtleastAmount};
+use fedimint_core::{config::FederationId, core::OperationId, Amount};
+use fedimint_mint_client::{
+    MintClientModule, OOBNotes, ReissueExternalNotesState, SelectNotesWithAtleastAmount,
+    SelectNotesWithExactAmount,
+};
 use futures_util::StreamExt;
+use lightning_invoice::Bolt11Invoice;
+use multimint::client::first_module;
+use multimint::MultiMint;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use tracing::{debug, info, warn};
 
+/// How long the outgoing notes stay valid before the server reclaims them
+const SPEND_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Number of times creating the outgoing notes is tried before refunding the user
+const SPEND_ATTEMPTS: u32 = 4;
+
+/// Delay before the first retry, doubled after each attempt
+const SPEND_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Ecash sent for a swap, used by both `POST /swap` and `POST /swap/ecash-to-ln`
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SwapPayload {
-    pub to_federation_id: FederationId,
-    pub from_federation_id: FederationId,
+    /// A quote fixing the federations and amounts of the swap
+    pub quote: SignedQuote,
     pub from_ecash: OOBNotes,
+    /// Lets a client retry a swap whose response it lost without swapping twice
+    pub idempotency_key: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SwapResponse {
+    pub swap_id: OperationId,
+    /// `ok`, `awaiting_payment` until `invoice` is paid, `paid` once the user's invoice was paid, or `refunded` when `ecash` is a refund on the source federation
     pub status: String,
-    pub ecash: OOBNotes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ecash: Option<OOBNotes>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invoice: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preimage: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SwapResponse {
+    fn new(swap_id: OperationId, status: &str) -> Self {
+        Self {
+            swap_id,
+            status: status.to_string(),
+            ecash: None,
+            invoice: None,
+            preimage: None,
+            error: None,
+        }
+    }
 }
 
 #[axum_macros::debug_handler]
@@ -28,92 +81,550 @@ pub async fn handle_swap(
     State(state): State<AppState>,
     Json(req): Json<SwapPayload>,
 ) -> Result<Json<Value>, AppError> {
-    let clients = state.multimint.clients.lock().await;
-    let (from_client, to_client) = get_clients(&clients, &req)?;
+    swap_ecash(state, req, SwapMode::Ecash).await
+}
+
+/// Take the user's ecash and pay out as agreed in the quote, for the swap modes where the user sends ecash
+pub async fn swap_ecash(
+    state: AppState,
+    req: SwapPayload,
+    mode: SwapMode,
+) -> Result<Json<Value>, AppError> {
+    if let Some(key) = &req.idempotency_key {
+        if let Some((swap_id, record)) = state.swaps.get_by_idempotency_key(key).await {
+            return replay_swap(swap_id, record, &req.quote);
+        }
+    }
+
+    state
+        .quoter
+        .verify(&req.quote, mode)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, e))?;
+    let quote = &req.quote.quote;
+
+    check_ecash(quote, &req.from_ecash)?;
+
+    let (from_client, to_client) = get_clients(
+        &state.multimint,
+        &quote.from_federation_id,
+        &quote.to_federation_id,
+    )
+    .await?;
+    state
+        .liquidity
+        .check_swap(
+            quote.from_federation_id,
+            quote.to_federation_id,
+            quote.amount_in,
+            quote.amount_out,
+        )
+        .await?;
+
+    let now = unix_now();
+    let record = SwapRecord {
+        mode,
+        from_federation_id: quote.from_federation_id,
+        to_federation_id: quote.to_federation_id,
+        amount_in: quote.amount_in,
+        amount_out: quote.amount_out,
+        state: SwapState::Pending,
+        invoice: quote.invoice.clone(),
+        idempotency_key: req.idempotency_key.clone(),
+        created_at: now,
+        updated_at: now,
+    };
+    let swap_id = quote.id;
+    state
+        .swaps
+        .create(swap_id, &record)
+        .await
+        .map_err(|e| AppError::new(StatusCode::CONFLICT, e).with_code("quote_already_used"))?;
 
-    if from_client.federation_id() == to_client.federation_id() {
+    let swap = tokio::spawn(reissue_and_pay_out(
+        state.swaps.clone(),
+        swap_id,
+        req.from_ecash,
+        from_client,
+        to_client,
+    ));
+    swap.await.map_err(|e| {
+        AppError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            anyhow!("The swap task failed: {e}"),
+        )
+        .with_details(json!({ "swap_id": swap_id }))
+    })?
+}
+
+/// Check that the user's ecash is exactly what the quote asks for
+fn check_ecash(quote: &Quote, ecash: &OOBNotes) -> Result<(), AppError> {
+    if ecash.federation_id_prefix() != quote.from_federation_id.to_prefix() {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            anyhow!("The ecash does not belong to the from_federation_id of the quote"),
+        ));
+    }
+
+    if ecash.total_amount() != quote.amount_in {
         return Err(AppError::new(
             StatusCode::BAD_REQUEST,
-            anyhow!("Cannot swap with yourself"),
+            anyhow!(
+                "The ecash is worth {}, the quote is for {}",
+                ecash.total_amount(),
+                quote.amount_in
+            ),
         ));
     }
 
-    let amount = req.from_ecash.total_amount();
-    check_balance(&to_client, amount).await?;
-    let notes = perform_swap(to_client, from_client, req.from_ecash, amount).await?;
-    Ok(Json(json!(SwapResponse {
-        status: "ok".to_string(),
-        ecash: notes,
+    Ok(())
+}
+
+/// Reissue the incoming ecash and pay out. Runs on its own task so a client disconnecting can't interrupt the swap between spending and recording the outgoing ecash
+async fn reissue_and_pay_out(
+    swaps: SwapStore,
+    swap_id: OperationId,
+    from_ecash: OOBNotes,
+    from_client: ClientArc,
+    to_client: ClientArc,
+) -> Result<Json<Value>, AppError> {
+    if let Err(e) = reissue_notes(&from_client, from_ecash).await {
+        let error = e.error.to_string();
+        swaps
+            .update_state(swap_id, SwapState::Failed { error })
+            .await?;
+        return Err(e.with_details(json!({ "swap_id": swap_id })));
+    }
+    let record = swaps.update_state(swap_id, SwapState::Reissued).await?;
+
+    let record = pay_out(&swaps, swap_id, &record, &from_client, &to_client).await?;
+    swap_response(swap_id, record)
+}
+
+/// Answer a retried request with the outcome of the swap its idempotency key started
+pub fn replay_swap(
+    swap_id: OperationId,
+    record: SwapRecord,
+    quote: &SignedQuote,
+) -> Result<Json<Value>, AppError> {
+    if swap_id != quote.quote.id {
+        return Err(AppError::new(
+            StatusCode::CONFLICT,
+            anyhow!("The idempotency key was used for a different swap"),
+        ));
+    }
+
+    swap_response(swap_id, record)
+}
+
+/// Answer with what a swap handed out, or the reason it has nothing yet
+pub fn swap_response(swap_id: OperationId, record: SwapRecord) -> Result<Json<Value>, AppError> {
+    let details = json!({ "swap_id": swap_id });
+    match record.state {
+        SwapState::Completed { notes } => Ok(Json(json!(SwapResponse {
+            ecash: Some(notes),
+            ..SwapResponse::new(swap_id, "ok")
+        }))),
+        SwapState::AwaitingPayment { invoice, .. } => Ok(Json(json!(SwapResponse {
+            invoice: Some(invoice),
+            ..SwapResponse::new(swap_id, "awaiting_payment")
+        }))),
+        SwapState::Paid { preimage } => Ok(Json(json!(SwapResponse {
+            preimage: Some(preimage),
+            ..SwapResponse::new(swap_id, "paid")
+        }))),
+        SwapState::Refunded { notes, error } => Ok(Json(json!(SwapResponse {
+            ecash: Some(notes),
+            error: Some(error),
+            ..SwapResponse::new(swap_id, "refunded")
+        }))),
+        SwapState::Owed { error } => Err(AppError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            anyhow!("The swap could not pay out, claim it later with POST /swap/:id/claim: {error}"),
+        )
+        .with_code("swap_owed")
+        .with_details(details)),
+        SwapState::Failed { error } => {
+            Err(AppError::new(StatusCode::BAD_REQUEST, anyhow!(error)).with_details(details))
+        }
+        SwapState::Pending | SwapState::Reissued | SwapState::PayingOut { .. } => {
+            Err(AppError::new(
+                StatusCode::CONFLICT,
+                anyhow!("The swap is still in progress"),
+            )
+            .with_details(details))
+        }
+    }
+}
+
+/// Retry paying out a swap that is owed to the user. Swaps interrupted by a restart are resumed when the server starts instead.
+///
+/// Anyone holding the swap id can claim it: it is only ever handed to the user who made the swap. The invoice of an `ecash_to_ln` swap is never paid twice by a claim: a payment that was started is awaited, and its ecash refunded if it failed.
+#[axum_macros::debug_handler]
+pub async fn handle_claim_swap(
+    State(state): State<AppState>,
+    Path(swap_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let swap_id = parse_swap_id(&swap_id)?;
+    let record = get_swap(&state.swaps, swap_id).await?;
+
+    if !matches!(record.state, SwapState::Owed { .. }) {
+        return Err(AppError::new(
+            StatusCode::CONFLICT,
+            anyhow!("The swap has nothing to claim"),
+        )
+        .with_code("swap_not_claimable")
+        .with_details(json!({ "swap_id": swap_id, "swap": record })));
+    }
+
+    let (from_client, to_client) = get_clients(
+        &state.multimint,
+        &record.from_federation_id,
+        &record.to_federation_id,
+    )
+    .await?;
+    let record = pay_out(&state.swaps, swap_id, &record, &from_client, &to_client).await?;
+    swap_response(swap_id, record)
+}
+
+#[axum_macros::debug_handler]
+pub async fn handle_get_swap(
+    State(state): State<AppState>,
+    Path(swap_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    let swap_id = parse_swap_id(&swap_id)?;
+    let record = get_swap(&state.swaps, swap_id).await?;
+
+    Ok(Json(json!({
+        "swap_id": swap_id,
+        "swap": record,
     })))
 }
 
-fn get_clients(
-    clients: &BTreeMap<FederationId, ClientArc>,
-    req: &SwapPayload,
+pub fn parse_swap_id(swap_id: &str) -> Result<OperationId, AppError> {
+    OperationId::from_str(swap_id)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, anyhow!("Invalid swap id: {e}")))
+}
+
+pub async fn get_swap(swaps: &SwapStore, swap_id: OperationId) -> Result<SwapRecord, AppError> {
+    swaps.get(swap_id).await.ok_or_else(|| {
+        AppError::new(StatusCode::NOT_FOUND, anyhow!("Unknown swap: {swap_id:?}"))
+    })
+}
+
+pub async fn get_clients(
+    multimint: &MultiMint,
+    from_federation_id: &FederationId,
+    to_federation_id: &FederationId,
 ) -> Result<(ClientArc, ClientArc), AppError> {
-    let from_client = clients.get(&req.from_federation_id).ok_or_else(|| {
-        AppError::new(
-            StatusCode::BAD_REQUEST,
-            anyhow!("This swap does not have a client for the from_federation_id"),
-        )
-    })?;
-    let to_client = clients.get(&req.to_federation_id).ok_or_else(|| {
+    let from_client = multimint
+        .get(from_federation_id)
+        .await
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!("This swap does not have a client for the from_federation_id"),
+            )
+        })?;
+    let to_client = multimint.get(to_federation_id).await.ok_or_else(|| {
         AppError::new(
             StatusCode::BAD_REQUEST,
             anyhow!("This swap does not have a client for the to_federation_id"),
         )
     })?;
-    Ok((from_client.clone(), to_client.clone()))
+    Ok((from_client, to_client))
 }
 
-async fn check_balance(to_client: &ClientArc, amount: Amount) -> Result<(), AppError> {
-    let to_client_ecash = to_client.get_balance().await;
-    if to_client_ecash < amount {
-        return Err(AppError::new(
-            StatusCode::BAD_REQUEST,
-            anyhow!("Not enough ecash to perform this swap"),
-        ));
+/// Pay out a swap whose incoming ecash or Lightning payment was received: the outgoing ecash or invoice payment if it can be made, else a refund of the incoming ecash on the source federation, else an owed balance the user can claim later.
+///
+/// The swap is first moved to `PayingOut`, failing with a conflict if another request got to it first. A swap already `PayingOut` was interrupted and is resumed.
+pub async fn pay_out(
+    swaps: &SwapStore,
+    swap_id: OperationId,
+    record: &SwapRecord,
+    from_client: &ClientArc,
+    to_client: &ClientArc,
+) -> Result<SwapRecord, AppError> {
+    let record = match record.state {
+        SwapState::PayingOut { .. } => record.clone(),
+        _ => {
+            let operation_id = match record.mode {
+                SwapMode::EcashToLn => user_invoice(record)
+                    .ok()
+                    .map(|invoice| payment_operation_id(&invoice)),
+                SwapMode::Ecash | SwapMode::LnToEcash => None,
+            };
+            swaps
+                .compare_and_update_state(
+                    swap_id,
+                    &record.state,
+                    SwapState::PayingOut { operation_id },
+                )
+                .await
+                .map_err(|e| AppError::new(StatusCode::CONFLICT, e))?
+        }
     };
-    Ok(())
+
+    // Notes spent by an interrupted attempt were never handed out, the client reclaims them after `SPEND_TIMEOUT`
+    let outcome = match record.mode {
+        SwapMode::Ecash | SwapMode::LnToEcash => spend_with_retry(to_client, record.amount_out)
+            .await
+            .map(|notes| SwapState::Completed { notes }),
+        SwapMode::EcashToLn => pay_user_invoice(to_client, &record)
+            .await
+            .map(|preimage| SwapState::Paid { preimage }),
+    };
+
+    let state = match outcome {
+        Ok(state) => state,
+        // A Lightning payment can't be refunded, the user claims the ecash later instead
+        Err(e) if record.mode == SwapMode::LnToEcash => {
+            warn!("Swap {swap_id:?} could not create the outgoing ecash, recording it as owed: {e}");
+            SwapState::Owed {
+                error: e.to_string(),
+            }
+        }
+        Err(e) => {
+            warn!("Swap {swap_id:?} could not pay out, refunding: {e}");
+            refund_or_owe(swap_id, &record, from_client, e.to_string()).await
+        }
+    };
+
+    Ok(swaps.update_state(swap_id, state).await?)
 }
 
-async fn perform_swap(
-    to_client: ClientArc,
-    from_client: ClientArc,
-    from_ecash: OOBNotes,
-    amount: Amount,
-) -> Result<OOBNotes, AppError> {
-    let from_mint = from_client.get_first_module::<MintClientModule>();
-    let to_mint = to_client.get_first_module::<MintClientModule>();
+/// Finish paying out, in the background, the swaps the server was paying out when it stopped
+pub async fn resume_interrupted_swaps(state: &AppState) {
+    for (swap_id, record) in state.swaps.interrupted().await {
+        let state = state.clone();
+        tokio::spawn(async move {
+            info!("Resuming interrupted swap {swap_id:?}");
+            if let Err(e) = resume_swap(&state, swap_id, &record).await {
+                warn!("Swap {swap_id:?} could not be resumed: {:?}", e.error);
+            }
+        });
+    }
+}
 
-    let operation_id = to_mint.reissue_external_notes(from_ecash, ()).await?;
-    let mut updates = from_mint
-        .subscribe_reissue_external_notes(operation_id)
+async fn resume_swap(
+    state: &AppState,
+    swap_id: OperationId,
+    record: &SwapRecord,
+) -> Result<SwapRecord, AppError> {
+    let (from_client, to_client) = get_clients(
+        &state.multimint,
+        &record.from_federation_id,
+        &record.to_federation_id,
+    )
+    .await?;
+    pay_out(&state.swaps, swap_id, record, &from_client, &to_client).await
+}
+
+/// Hand the incoming ecash back on the source federation, or record it as owed if even that fails
+async fn refund_or_owe(
+    swap_id: OperationId,
+    record: &SwapRecord,
+    from_client: &ClientArc,
+    error: String,
+) -> SwapState {
+    match spend_with_retry(from_client, record.amount_in).await {
+        Ok(notes) => SwapState::Refunded { notes, error },
+        Err(refund_error) => {
+            warn!("Swap {swap_id:?} could not be refunded, recording it as owed: {refund_error}");
+            SwapState::Owed { error }
+        }
+    }
+}
+
+fn user_invoice(record: &SwapRecord) -> Result<Bolt11Invoice> {
+    let invoice = record
+        .invoice
+        .as_deref()
+        .ok_or_else(|| anyhow!("The swap has no invoice to pay"))?;
+    Bolt11Invoice::from_str(invoice).map_err(|e| anyhow!("Invalid invoice: {e}"))
+}
+
+/// Pay the user's invoice, or wait for the outcome of a payment of it started before the swap was interrupted
+async fn pay_user_invoice(client: &ClientArc, record: &SwapRecord) -> Result<String> {
+    let invoice = user_invoice(record)?;
+    if let Some(preimage) = await_payment(client, payment_operation_id(&invoice)).await? {
+        return Ok(preimage);
+    }
+    pay_invoice(client, invoice).await
+}
+
+/// Spend `amount` out of a client, retrying with exponential backoff
+async fn spend_with_retry(client: &ClientArc, amount: Amount) -> Result<OOBNotes> {
+    let mut delay = SPEND_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        match spend_outgoing(client, amount).await {
+            Ok(notes) => return Ok(notes),
+            Err(e) if attempt >= SPEND_ATTEMPTS => return Err(e),
+            Err(e) => {
+                warn!("Failed to spend {amount} (attempt {attempt}/{SPEND_ATTEMPTS}), retrying in {delay:?}: {e}");
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Reissue notes with the client of the federation that issued them, waiting until the federation accepted them
+pub async fn reissue_notes(client: &ClientArc, notes: OOBNotes) -> Result<(), AppError> {
+    let mint = first_module::<MintClientModule>(client)?;
+
+    let operation_id = mint
+        .reissue_external_notes(notes, ())
         .await
-        .unwrap()
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, e))?;
+    let mut updates = mint
+        .subscribe_reissue_external_notes(operation_id)
+        .await?
         .into_stream();
 
-    let mut notes = None;
-
     while let Some(update) = updates.next().await {
         match update {
-            fedimint_mint_client::ReissueExternalNotesState::Done => {
-                let timeout = Duration::from_secs(3600);
-                let (_, new_notes) = to_mint
-                    .spend_notes_with_selector(&SelectNotesWithAtleastAmount, amount, timeout, ())
-                    .await?;
-                notes = Some(new_notes);
-            }
-            fedimint_mint_client::ReissueExternalNotesState::Failed(e) => {
-                return Err(AppError::new(StatusCode::INTERNAL_SERVER_ERROR, anyhow!(e)));
+            ReissueExternalNotesState::Done => return Ok(()),
+            ReissueExternalNotesState::Failed(e) => {
+                return Err(AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    anyhow!("Failed to reissue the ecash: {e}"),
+                ));
             }
             _ => {}
         }
     }
-    notes.ok_or_else(|| {
-        AppError::new(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            anyhow!("Failed to get notes after swap"),
-        )
-    })
+
+    Err(AppError::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        anyhow!("Reissue update stream ended before the ecash was reissued"),
+    ))
+}
+
+/// Spend exactly `amount` out of a client, first re-denominating its notes if they can't make up the amount
+async fn spend_outgoing(client: &ClientArc, amount: Amount) -> Result<OOBNotes> {
+    let mint = first_module::<MintClientModule>(client)?;
+    let summary = mint
+        .get_wallet_summary(&mut mint.db.begin_transaction_nc().await)
+        .await;
+    if !fits_exactly(
+        summary
+            .iter()
+            .map(|(denomination, count)| (denomination, *count)),
+        amount,
+    ) {
+        debug!("Cannot spend exactly {amount}, re-denominating");
+        redenominate(client, amount).await?;
+    }
+
+    let (_, notes) = mint
+        .spend_notes_with_selector(&SelectNotesWithExactAmount, amount, SPEND_TIMEOUT, ())
+        .await?;
+    Ok(notes)
+}
+
+/// Whether notes of these denominations and counts make up exactly `amount` when picked largest first, the way `SelectNotesWithExactAmount` picks them
+fn fits_exactly(notes: impl IntoIterator<Item = (Amount, usize)>, amount: Amount) -> bool {
+    let mut notes = notes.into_iter().collect::<Vec<_>>();
+    notes.sort_by_key(|(denomination, _)| std::cmp::Reverse(*denomination));
+
+    let mut remaining = amount.msats;
+    for (denomination, count) in notes {
+        if denomination.msats == 0 {
+            continue;
+        }
+        let taken = (remaining / denomination.msats).min(count as u64);
+        remaining -= taken * denomination.msats;
+    }
+    remaining == 0
+}
+
+/// Break notes worth at least `amount` into smaller ones by spending them and reissuing them back into the same client
+async fn redenominate(client: &ClientArc, amount: Amount) -> Result<()> {
+    let mint = first_module::<MintClientModule>(client)?;
+    let (_, notes) = mint
+        .spend_notes_with_selector(&SelectNotesWithAtleastAmount, amount, SPEND_TIMEOUT, ())
+        .await?;
+    reissue_notes(client, notes).await.map_err(|e| e.error)
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::TieredMulti;
+    use multimint::test_utils::{check_cases, federation_id};
+
+    use super::*;
+
+    fn notes(denominations: &[(u64, usize)]) -> Vec<(Amount, usize)> {
+        denominations
+            .iter()
+            .map(|(msats, count)| (Amount::from_msats(*msats), *count))
+            .collect()
+    }
+
+    fn quote(from_federation_id: FederationId, amount_in: Amount) -> Quote {
+        Quote {
+            id: OperationId([1; 32]),
+            mode: SwapMode::Ecash,
+            from_federation_id,
+            to_federation_id: federation_id(2),
+            amount_in,
+            fee: Amount::ZERO,
+            amount_out: amount_in,
+            invoice: None,
+            expires_at: 0,
+        }
+    }
+
+    #[test]
+    fn ecash_must_match_the_quote() {
+        let federation = federation_id(1);
+        let other = federation_id(3);
+        let ecash = OOBNotes::new(federation.to_prefix(), TieredMulti::default());
+        let rejected = Some(StatusCode::BAD_REQUEST);
+        let cases = [
+            ((federation, Amount::ZERO), None),
+            // Ecash of another federation than the quote's source
+            ((other, Amount::ZERO), rejected),
+            ((federation, Amount::from_sats(1)), rejected),
+        ];
+
+        check_cases(cases, |(from_federation_id, amount_in)| {
+            check_ecash(&quote(from_federation_id, amount_in), &ecash)
+                .err()
+                .map(|e| e.status)
+        });
+    }
+
+    #[test]
+    fn exact_amount_is_spent_directly() {
+        let cases = [
+            ((vec![(1024, 1), (64, 2), (2, 1)], 1154), true),
+            ((vec![(1024, 1), (512, 2)], 1024), true),
+            ((vec![(512, 2)], 1024), true),
+            ((vec![(2, 1), (1024, 1)], 1026), true),
+            ((vec![(1024, 1)], 0), true),
+            ((vec![], 0), true),
+        ];
+
+        check_cases(cases, |(denominations, amount_msat)| {
+            fits_exactly(notes(&denominations), Amount::from_msats(amount_msat))
+        });
+    }
+
+    #[test]
+    fn notes_not_making_up_the_amount_are_redenominated() {
+        let cases = [
+            ((vec![(1024, 1)], 1000), false),
+            ((vec![(1024, 1), (512, 2)], 1000), false),
+            ((vec![(64, 2)], 129), false),
+            ((vec![(1024, 1)], 2048), false),
+            ((vec![], 1), false),
+        ];
+
+        check_cases(cases, |(denominations, amount_msat)| {
+            fits_exactly(notes(&denominations), Amount::from_msats(amount_msat))
+        });
+    }
 }
@@ -0,0 +1,145 @@
+//! Lightning payments made and received by the swap server
+
+use anyhow::{anyhow, Result};
+use fedimint_client::ClientArc;
+use fedimint_core::core::OperationId;
+use fedimint_core::Amount;
+use fedimint_ln_client::{
+    InternalPayState, LightningClientModule, LightningOperationMeta, LightningOperationMetaVariant,
+    LnPayState, LnReceiveState, OutgoingLightningPayment, PayType,
+};
+use futures_util::StreamExt;
+use lightning_invoice::Bolt11Invoice;
+use multimint::client::first_module;
+
+/// The fee the client's gateway charges for paying `amount`
+pub async fn gateway_fee(client: &ClientArc, amount: Amount) -> Result<Amount> {
+    let gateway = first_module::<LightningClientModule>(client)?
+        .select_active_gateway()
+        .await?;
+
+    let proportional =
+        u128::from(amount.msats) * u128::from(gateway.fees.proportional_millionths) / 1_000_000;
+    Ok(Amount::from_msats(
+        u64::from(gateway.fees.base_msat) + proportional as u64,
+    ))
+}
+
+pub async fn create_invoice(
+    client: &ClientArc,
+    amount: Amount,
+    description: String,
+    expiry_secs: u64,
+) -> Result<(OperationId, Bolt11Invoice)> {
+    let lightning_module = first_module::<LightningClientModule>(client)?;
+    lightning_module.select_active_gateway().await?;
+    lightning_module
+        .create_bolt11_invoice(amount, description, Some(expiry_secs), ())
+        .await
+}
+
+/// Wait until the invoice of a receive operation was paid and the ecash claimed
+pub async fn await_receive(client: &ClientArc, operation_id: OperationId) -> Result<()> {
+    let mut updates = first_module::<LightningClientModule>(client)?
+        .subscribe_ln_receive(operation_id)
+        .await?
+        .into_stream();
+
+    while let Some(update) = updates.next().await {
+        match update {
+            LnReceiveState::Claimed => return Ok(()),
+            LnReceiveState::Canceled { reason } => {
+                return Err(anyhow!("The payment was canceled: {reason:?}"))
+            }
+            _ => {}
+        }
+    }
+
+    Err(anyhow!(
+        "Lightning update stream ended before the payment was received"
+    ))
+}
+
+/// The operation id of paying an invoice, known before paying it: the client keys Lightning payments by their payment hash
+pub fn payment_operation_id(invoice: &Bolt11Invoice) -> OperationId {
+    OperationId(invoice.payment_hash().into_inner())
+}
+
+/// Pay an invoice and wait for the outcome, returning the preimage.
+///
+/// A failed payment is only reported once its funds are back in the client.
+pub async fn pay_invoice(client: &ClientArc, invoice: Bolt11Invoice) -> Result<String> {
+    let lightning_module = first_module::<LightningClientModule>(client)?;
+    lightning_module.select_active_gateway().await?;
+
+    let OutgoingLightningPayment { payment_type, .. } =
+        lightning_module.pay_bolt11_invoice(invoice, ()).await?;
+
+    await_payment_type(client, payment_type).await
+}
+
+/// Wait for the outcome of a payment started before, returning the preimage, or `None` if the client never started the operation
+pub async fn await_payment(
+    client: &ClientArc,
+    operation_id: OperationId,
+) -> Result<Option<String>> {
+    let Some(entry) = client.operation_log().get_operation(operation_id).await else {
+        return Ok(None);
+    };
+
+    let payment_type = match entry.meta::<LightningOperationMeta>().variant {
+        LightningOperationMetaVariant::Pay(pay) if pay.is_internal_payment => {
+            PayType::Internal(operation_id)
+        }
+        LightningOperationMetaVariant::Pay(_) => PayType::Lightning(operation_id),
+        _ => return Err(anyhow!("Operation {operation_id:?} is not a payment")),
+    };
+    await_payment_type(client, payment_type).await.map(Some)
+}
+
+async fn await_payment_type(client: &ClientArc, payment_type: PayType) -> Result<String> {
+    let lightning_module = first_module::<LightningClientModule>(client)?;
+
+    match payment_type {
+        PayType::Internal(operation_id) => {
+            let mut updates = lightning_module
+                .subscribe_internal_pay(operation_id)
+                .await?
+                .into_stream();
+
+            while let Some(update) = updates.next().await {
+                match update {
+                    InternalPayState::Preimage(preimage) => return Ok(hex::encode(preimage.0)),
+                    InternalPayState::Funding => {}
+                    failed => return Err(anyhow!("Internal payment failed: {failed:?}")),
+                }
+            }
+        }
+        PayType::Lightning(operation_id) => {
+            let mut updates = lightning_module
+                .subscribe_ln_pay(operation_id)
+                .await?
+                .into_stream();
+
+            while let Some(update) = updates.next().await {
+                match update {
+                    LnPayState::Success { preimage } => return Ok(preimage),
+                    LnPayState::Refunded { gateway_error } => {
+                        return Err(anyhow!(
+                            "Payment failed and was refunded: {gateway_error:?}"
+                        ))
+                    }
+                    LnPayState::Canceled => return Err(anyhow!("Payment was canceled")),
+                    LnPayState::UnexpectedError { error_message } => {
+                        return Err(anyhow!("Payment failed: {error_message}"))
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "Lightning update stream ended before the payment completed"
+    ))
+}
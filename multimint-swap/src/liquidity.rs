@@ -0,0 +1,293 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use axum::http::StatusCode;
+use fedimint_core::config::FederationId;
+use fedimint_core::Amount;
+use multimint::events::MultiMintEvent;
+use multimint::MultiMint;
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::config::SwapConfig;
+use crate::error::AppError;
+
+/// Number of liquidity alerts buffered for slow subscribers
+const ALERT_CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReserveLevel {
+    /// The balance is below the minimum reserve
+    Low,
+    Ok,
+    /// The balance is above the maximum reserve
+    High,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReserveStatus {
+    pub federation_id: FederationId,
+    pub balance: Amount,
+    pub min_reserve: Amount,
+    pub max_reserve: Option<Amount>,
+    pub target: Amount,
+    pub level: ReserveLevel,
+}
+
+impl ReserveStatus {
+    /// The ecash that can be paid out of the federation without going below its reserve
+    pub fn available(&self) -> Amount {
+        Amount::from_msats(self.balance.msats.saturating_sub(self.min_reserve.msats))
+    }
+
+    /// Whether `amount_out` can be paid out of the federation without going below its reserve, once `amount_in` was received into it
+    pub fn can_pay_out(&self, amount_in: Amount, amount_out: Amount) -> bool {
+        self.balance.msats + amount_in.msats >= amount_out.msats + self.min_reserve.msats
+    }
+}
+
+/// Sent when the balance of a federation crosses one of its reserve thresholds
+#[derive(Debug, Clone, Serialize)]
+pub struct LiquidityAlert {
+    #[serde(flatten)]
+    pub status: ReserveStatus,
+    pub previous_level: ReserveLevel,
+}
+
+/// Checks swaps against the configured amount limits and reserves, and watches the balances for reserve crossings
+#[derive(Debug, Clone)]
+pub struct Liquidity {
+    multimint: MultiMint,
+    config: SwapConfig,
+    levels: Arc<Mutex<BTreeMap<FederationId, ReserveLevel>>>,
+    alerts: broadcast::Sender<LiquidityAlert>,
+}
+
+impl Liquidity {
+    pub fn new(multimint: MultiMint, config: SwapConfig) -> Self {
+        let (alerts, _) = broadcast::channel(ALERT_CHANNEL_CAPACITY);
+        Self {
+            multimint,
+            config,
+            levels: Arc::new(Mutex::new(BTreeMap::new())),
+            alerts,
+        }
+    }
+
+    pub fn subscribe_alerts(&self) -> broadcast::Receiver<LiquidityAlert> {
+        self.alerts.subscribe()
+    }
+
+    fn status_for(&self, federation_id: FederationId, balance: Amount) -> ReserveStatus {
+        let reserve = self.config.reserve(federation_id);
+        let level = if balance.msats < reserve.min_msat {
+            ReserveLevel::Low
+        } else if reserve.max_msat.is_some_and(|max_msat| balance.msats > max_msat) {
+            ReserveLevel::High
+        } else {
+            ReserveLevel::Ok
+        };
+
+        ReserveStatus {
+            federation_id,
+            balance,
+            min_reserve: Amount::from_msats(reserve.min_msat),
+            max_reserve: reserve.max_msat.map(Amount::from_msats),
+            target: Amount::from_msats(reserve.target_msat()),
+            level,
+        }
+    }
+
+    pub async fn status(&self, federation_id: FederationId) -> anyhow::Result<ReserveStatus> {
+        let client = self.multimint.get_or_err(&federation_id).await?;
+        Ok(self.status_for(federation_id, client.get_balance().await))
+    }
+
+    pub async fn statuses(&self) -> Vec<ReserveStatus> {
+        let clients = self.multimint.clients.lock().await.clone();
+        let mut statuses = Vec::new();
+        for (federation_id, client) in clients {
+            statuses.push(self.status_for(federation_id, client.get_balance().await));
+        }
+        statuses
+    }
+
+    /// Reject swaps outside the amount limits of the pair, or that would take the destination federation below its reserve
+    pub async fn check_swap(
+        &self,
+        from_federation_id: FederationId,
+        to_federation_id: FederationId,
+        amount_in: Amount,
+        amount_out: Amount,
+    ) -> Result<(), AppError> {
+        let (min_amount, max_amount) = self
+            .config
+            .amount_limits(from_federation_id, to_federation_id);
+        if amount_in < min_amount || max_amount.is_some_and(|max_amount| amount_in > max_amount) {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                anyhow!("The amount of {amount_in} is outside the limits of this pair"),
+            )
+            .with_code("amount_out_of_range")
+            .with_details(json!({
+                "min_amount": min_amount,
+                "max_amount": max_amount,
+            })));
+        }
+
+        // A Lightning swap receives into the federation it pays out of, so the incoming amount funds the payout
+        let received = if from_federation_id == to_federation_id {
+            amount_in
+        } else {
+            Amount::ZERO
+        };
+        let status = self.status(to_federation_id).await?;
+        if !status.can_pay_out(received, amount_out) {
+            return Err(AppError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                anyhow!("Not enough liquidity to perform this swap"),
+            )
+            .with_code("insufficient_liquidity"));
+        }
+
+        Ok(())
+    }
+
+    /// Spawn a background task following the balance changes of the multimint and sending an alert whenever a federation's reserve level changes
+    pub fn spawn_monitor(&self) -> JoinHandle<()> {
+        let liquidity = self.clone();
+        let mut events = self.multimint.subscribe_events();
+
+        tokio::spawn(async move {
+            for status in liquidity.statuses().await {
+                liquidity.update_level(status).await;
+            }
+
+            loop {
+                match events.recv().await {
+                    Ok(MultiMintEvent::BalanceChanged {
+                        federation_id,
+                        balance,
+                    }) => {
+                        let status = liquidity.status_for(federation_id, balance);
+                        liquidity.update_level(status).await;
+                    }
+                    Ok(MultiMintEvent::FederationLeft { federation_id, .. }) => {
+                        liquidity.levels.lock().await.remove(&federation_id);
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Liquidity monitor lagged behind, skipped {skipped} events");
+                        for status in liquidity.statuses().await {
+                            liquidity.update_level(status).await;
+                        }
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    async fn update_level(&self, status: ReserveStatus) {
+        let previous_level = self
+            .levels
+            .lock()
+            .await
+            .insert(status.federation_id, status.level)
+            .unwrap_or(ReserveLevel::Ok);
+        if previous_level == status.level {
+            return;
+        }
+
+        match status.level {
+            ReserveLevel::Low => warn!(
+                "Reserve of federation {} is low: {} < {}",
+                status.federation_id, status.balance, status.min_reserve
+            ),
+            ReserveLevel::High => warn!(
+                "Federation {} is over-funded: {}",
+                status.federation_id, status.balance
+            ),
+            ReserveLevel::Ok => info!(
+                "Reserve of federation {} is back within bounds: {}",
+                status.federation_id, status.balance
+            ),
+        }
+
+        // Nobody listening is fine
+        let _ = self.alerts.send(LiquidityAlert {
+            status,
+            previous_level,
+        });
+    }
+}
+
+/// Round an amount down to a power of ten sats, so advertising it doesn't reveal the exact balance
+pub fn liquidity_bucket(amount: Amount) -> Amount {
+    let sats = amount.msats / 1000;
+    if sats == 0 {
+        return Amount::ZERO;
+    }
+    Amount::from_sats(10u64.pow(sats.ilog10()))
+}
+
+#[cfg(test)]
+mod tests {
+    use multimint::test_utils::{check_cases, federation_id};
+
+    use super::*;
+
+    #[test]
+    fn payout_is_checked_against_the_reserve() {
+        let status = ReserveStatus {
+            federation_id: federation_id(1),
+            balance: Amount::from_sats(1_000),
+            min_reserve: Amount::from_sats(500),
+            max_reserve: None,
+            target: Amount::from_sats(500),
+            level: ReserveLevel::Ok,
+        };
+        let cases = [
+            ((0, 500), true),
+            ((0, 501), false),
+            // A self-funded swap only pays out its net amount
+            ((1_000, 1_500), true),
+            ((1_000, 1_501), false),
+            ((10_000, 9_900), true),
+        ];
+
+        check_cases(cases, |(amount_in_sat, amount_out_sat)| {
+            status.can_pay_out(
+                Amount::from_sats(amount_in_sat),
+                Amount::from_sats(amount_out_sat),
+            )
+        });
+    }
+
+    #[test]
+    fn liquidity_is_rounded_down_to_powers_of_ten_sats() {
+        let cases = [
+            (0, 0),
+            (1, 0),
+            (999, 0),
+            (1_000, 1_000),
+            (9_999, 1_000),
+            (10_000, 10_000),
+            (10_001, 10_000),
+            (99_999_999, 10_000_000),
+            (100_000_000, 100_000_000),
+            (123_456_789_000, 100_000_000_000),
+            (u64::MAX, 10_000_000_000_000_000_000),
+        ];
+
+        check_cases(cases, |balance_msat| {
+            liquidity_bucket(Amount::from_msats(balance_msat)).msats
+        });
+    }
+}
@@ -1,43 +1,116 @@
 use axum::{
+    middleware,
     routing::{get, post},
     Router,
 };
 
-// use tower_http::validate_request::ValidateRequestHeaderLayer;
 use anyhow::Result;
-use tracing::info;
+use tracing::{info, warn};
 
 pub mod config;
-pub mod error;
+pub mod db;
 pub mod handlers;
+pub mod lightning;
+pub mod liquidity;
+pub mod quotes;
+pub mod rebalancer;
+pub mod swaps;
 
-use config::CONFIG;
+pub use multimint_common::error;
 
-use crate::handlers::{info::handle_info, swap::handle_swap};
+use multimint_common::auth::{require_admin, AdminAuth};
+use multimint_common::config::Config;
+
+use crate::config::SwapConfig;
+use crate::handlers::{
+    info::{handle_admin_info, handle_info},
+    lightning::{
+        handle_quote_ecash_to_ln, handle_quote_ln_to_ecash, handle_swap_ecash_to_ln,
+        handle_swap_ln_to_ecash, resume_payment_watchers,
+    },
+    liquidity::{handle_liquidity, handle_liquidity_events},
+    quote::handle_quote,
+    rebalance::handle_rebalances,
+    swap::{handle_claim_swap, handle_get_swap, handle_swap, resume_interrupted_swaps},
+};
+use crate::liquidity::Liquidity;
+use crate::quotes::Quoter;
+use crate::rebalancer::Rebalancer;
+use crate::swaps::SwapStore;
 
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub multimint: multimint::MultiMint,
+    pub swaps: SwapStore,
+    pub quoter: Quoter,
+    pub liquidity: Liquidity,
+    pub rebalancer: Rebalancer,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
-    dotenv::dotenv().ok();
+    let config = Config::load()?;
+    let swap_config = config.section::<SwapConfig>("swap")?;
+
+    let mut multimint = multimint::MultiMint::new(config.data_dir.clone()).await?;
+    let report = multimint
+        .reconcile(&config.invite_codes, config.leave_unlisted)
+        .await?;
+    if report.failed.is_empty() {
+        info!("Reconciled federations: {report}");
+    } else {
+        warn!("Reconciled federations: {report}");
+    }
+
+    if let Some(interval) = config.meta_refresh_interval() {
+        multimint.spawn_meta_refresh(interval);
+    }
+
+    let swaps = SwapStore::new(multimint.db().clone());
+    let liquidity = Liquidity::new(multimint.clone(), swap_config.clone());
+    liquidity.spawn_monitor();
+    let rebalancer = Rebalancer::new(
+        multimint.clone(),
+        liquidity.clone(),
+        swap_config.rebalance.clone(),
+    );
+    rebalancer.spawn();
+    let quoter = Quoter::new(swap_config, &config.password);
+    let admin_auth = AdminAuth::new(config.password.clone());
+    let state = AppState {
+        multimint,
+        swaps,
+        quoter,
+        liquidity,
+        rebalancer,
+    };
+    resume_payment_watchers(&state).await;
+    resume_interrupted_swaps(&state).await;
 
-    let multimint = multimint::MultiMint::new(CONFIG.data_dir.clone()).await?;
+    let admin_routes = Router::new()
+        .route("/info", get(handle_admin_info))
+        .route("/liquidity", get(handle_liquidity))
+        .route("/liquidity/events", get(handle_liquidity_events))
+        .route("/rebalances", get(handle_rebalances))
+        .route_layer(middleware::from_fn_with_state(admin_auth, require_admin));
 
-    let state = AppState { multimint };
     let app = Router::new()
         .route("/info", get(handle_info))
+        .route("/quote", post(handle_quote))
+        .route("/quote/ln-to-ecash", post(handle_quote_ln_to_ecash))
+        .route("/quote/ecash-to-ln", post(handle_quote_ecash_to_ln))
         .route("/swap", post(handle_swap))
+        .route("/swap/ln-to-ecash", post(handle_swap_ln_to_ecash))
+        .route("/swap/ecash-to-ln", post(handle_swap_ecash_to_ln))
+        .route("/swap/:id", get(handle_get_swap))
+        .route("/swap/:id/claim", post(handle_claim_swap))
+        .nest("/admin", admin_routes)
         .with_state(state);
 
-    let listener = tokio::net::TcpListener::bind(format!("{}:{}", CONFIG.host, CONFIG.port))
-        .await
-        .unwrap();
+    let listener = tokio::net::TcpListener::bind(config.bind_address()).await?;
 
-    info!("Listening on {}", CONFIG.port);
+    info!("Listening on {}", config.port);
 
     axum::serve(listener, app).await.unwrap();
 
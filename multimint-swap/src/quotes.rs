@@ -0,0 +1,325 @@
+use anyhow::{anyhow, Result};
+use fedimint_core::bitcoin_hashes::hmac::{Hmac, HmacEngine};
+use fedimint_core::bitcoin_hashes::{sha256, Hash, HashEngine};
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::Amount;
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+use crate::config::SwapConfig;
+use crate::db::SwapMode;
+use crate::swaps::unix_now;
+
+/// The terms of a swap, fixed before the user sends any ecash
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quote {
+    /// Becomes the id of the swap made with this quote, so a quote can only be used once
+    pub id: OperationId,
+    pub mode: SwapMode,
+    /// The federation the user's ecash or Lightning payment is received in
+    pub from_federation_id: FederationId,
+    /// The federation the user's ecash or Lightning payment is made from
+    pub to_federation_id: FederationId,
+    /// The exact amount of ecash, or of the Lightning payment, the user has to send
+    pub amount_in: Amount,
+    /// The server's fee, including the gateway fee of paying an invoice
+    pub fee: Amount,
+    /// The amount of ecash the user gets back, or of the invoice the server pays
+    pub amount_out: Amount,
+    /// The invoice the server pays in an `ecash_to_ln` swap
+    pub invoice: Option<String>,
+    /// Unix timestamp in seconds after which the quote can't be used
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedQuote {
+    #[serde(flatten)]
+    pub quote: Quote,
+    /// Hex HMAC-SHA256 of the quote under the server's quote key
+    pub signature: String,
+}
+
+/// Prices swaps with the configured fees and signs the quotes, so the server can check a quote it gets back without storing it
+#[derive(Debug, Clone)]
+pub struct Quoter {
+    config: SwapConfig,
+    key: [u8; 32],
+}
+
+impl Quoter {
+    pub fn new(config: SwapConfig, password: &str) -> Self {
+        let secret = config
+            .quote_secret
+            .clone()
+            .unwrap_or_else(|| format!("multimint-swap-quote:{password}"));
+        let key = sha256::Hash::hash(secret.as_bytes()).into_inner();
+        Self { config, key }
+    }
+
+    pub fn config(&self) -> &SwapConfig {
+        &self.config
+    }
+
+    /// Quote a swap of `amount_in` ecash, failing if the fee would eat all of it
+    pub fn quote(
+        &self,
+        from_federation_id: FederationId,
+        to_federation_id: FederationId,
+        amount_in: Amount,
+    ) -> Result<SignedQuote> {
+        if !self
+            .config
+            .is_supported(from_federation_id, to_federation_id)
+        {
+            return Err(anyhow!(
+                "Swaps from {from_federation_id} to {to_federation_id} are not supported"
+            ));
+        }
+
+        let fee = self
+            .config
+            .fee(from_federation_id, to_federation_id)
+            .amount(amount_in);
+        self.fee_deducted(
+            SwapMode::Ecash,
+            from_federation_id,
+            to_federation_id,
+            amount_in,
+            fee,
+        )
+    }
+
+    /// Quote a Lightning payment of `amount_in` for ecash of a federation
+    pub fn quote_ln_to_ecash(
+        &self,
+        federation_id: FederationId,
+        amount_in: Amount,
+    ) -> Result<SignedQuote> {
+        self.check_lightning_supported(federation_id)?;
+
+        let fee = self.config.lightning_fee.amount(amount_in);
+        self.fee_deducted(
+            SwapMode::LnToEcash,
+            federation_id,
+            federation_id,
+            amount_in,
+            fee,
+        )
+    }
+
+    /// Quote paying an invoice of `amount_out` for ecash of a federation, the fee including `gateway_fee`
+    pub fn quote_ecash_to_ln(
+        &self,
+        federation_id: FederationId,
+        invoice: String,
+        amount_out: Amount,
+        gateway_fee: Amount,
+    ) -> Result<SignedQuote> {
+        self.check_lightning_supported(federation_id)?;
+
+        let fee = self.config.lightning_fee.amount(amount_out) + gateway_fee;
+        self.sign_new(Quote {
+            id: OperationId::new_random(),
+            mode: SwapMode::EcashToLn,
+            from_federation_id: federation_id,
+            to_federation_id: federation_id,
+            amount_in: amount_out + fee,
+            fee,
+            amount_out,
+            invoice: Some(invoice),
+            expires_at: unix_now() + self.config.quote_ttl_secs,
+        })
+    }
+
+    fn check_lightning_supported(&self, federation_id: FederationId) -> Result<()> {
+        if !self.config.is_lightning_supported(federation_id) {
+            return Err(anyhow!(
+                "Lightning swaps with {federation_id} are not supported"
+            ));
+        }
+        Ok(())
+    }
+
+    fn fee_deducted(
+        &self,
+        mode: SwapMode,
+        from_federation_id: FederationId,
+        to_federation_id: FederationId,
+        amount_in: Amount,
+        fee: Amount,
+    ) -> Result<SignedQuote> {
+        if fee.msats >= amount_in.msats {
+            return Err(anyhow!(
+                "The fee of {fee} is not less than the amount of {amount_in}"
+            ));
+        }
+
+        self.sign_new(Quote {
+            id: OperationId::new_random(),
+            mode,
+            from_federation_id,
+            to_federation_id,
+            amount_in,
+            fee,
+            amount_out: Amount::from_msats(amount_in.msats - fee.msats),
+            invoice: None,
+            expires_at: unix_now() + self.config.quote_ttl_secs,
+        })
+    }
+
+    fn sign_new(&self, quote: Quote) -> Result<SignedQuote> {
+        let signature = self.sign(&quote)?;
+        Ok(SignedQuote { quote, signature })
+    }
+
+    /// Check that the quote was issued by this server for a swap of `mode` and has not expired
+    pub fn verify(&self, signed: &SignedQuote, mode: SwapMode) -> Result<()> {
+        let signature = self.sign(&signed.quote)?;
+        if !bool::from(signature.as_bytes().ct_eq(signed.signature.as_bytes())) {
+            return Err(anyhow!("Invalid quote signature"));
+        }
+        if signed.quote.mode != mode {
+            return Err(anyhow!("The quote is for a swap of another kind"));
+        }
+        if signed.quote.expires_at < unix_now() {
+            return Err(anyhow!("The quote expired"));
+        }
+        Ok(())
+    }
+
+    fn sign(&self, quote: &Quote) -> Result<String> {
+        let mut engine = HmacEngine::<sha256::Hash>::new(&self.key);
+        engine.input(&serde_json::to_vec(quote)?);
+        Ok(Hmac::<sha256::Hash>::from_engine(engine).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use multimint::test_utils::{check_cases, federation_id};
+
+    use super::*;
+    use crate::config::{Fee, PairConfig};
+
+    fn quoter() -> Quoter {
+        let config = SwapConfig {
+            default_fee: Fee {
+                base_msat: 1_000,
+                ppm: 10_000,
+            },
+            ..SwapConfig::default()
+        };
+        Quoter::new(config, "password")
+    }
+
+    fn ecash_quote(quoter: &Quoter) -> SignedQuote {
+        quoter
+            .quote(federation_id(1), federation_id(2), Amount::from_sats(100))
+            .unwrap()
+    }
+
+    #[test]
+    fn quote_deducts_fee() {
+        let signed = ecash_quote(&quoter());
+        assert_eq!(signed.quote.fee, Amount::from_msats(2_000));
+        assert_eq!(signed.quote.amount_out, Amount::from_msats(98_000));
+    }
+
+    #[test]
+    fn signed_quote_verifies() {
+        let quoter = quoter();
+        let signed = ecash_quote(&quoter);
+        assert!(quoter.verify(&signed, SwapMode::Ecash).is_ok());
+    }
+
+    #[test]
+    fn quote_survives_json_round_trip() {
+        let quoter = quoter();
+        let signed = ecash_quote(&quoter);
+        let json = serde_json::to_string(&signed).unwrap();
+        let parsed: SignedQuote = serde_json::from_str(&json).unwrap();
+        assert!(quoter.verify(&parsed, SwapMode::Ecash).is_ok());
+    }
+
+    #[test]
+    fn tampered_amount_is_rejected() {
+        let quoter = quoter();
+        let mut signed = ecash_quote(&quoter);
+        signed.quote.amount_out = signed.quote.amount_in;
+        assert!(quoter.verify(&signed, SwapMode::Ecash).is_err());
+    }
+
+    #[test]
+    fn quote_of_another_server_is_rejected() {
+        let signed = ecash_quote(&quoter());
+        let other = Quoter::new(SwapConfig::default(), "another password");
+        assert!(other.verify(&signed, SwapMode::Ecash).is_err());
+    }
+
+    #[test]
+    fn mode_mismatch_is_rejected() {
+        let quoter = quoter();
+        let signed = ecash_quote(&quoter);
+        assert!(quoter.verify(&signed, SwapMode::EcashToLn).is_err());
+        assert!(quoter.verify(&signed, SwapMode::LnToEcash).is_err());
+    }
+
+    #[test]
+    fn expired_quote_is_rejected() {
+        let quoter = quoter();
+        let mut quote = ecash_quote(&quoter).quote;
+        quote.expires_at = unix_now() - 1;
+        let signed = quoter.sign_new(quote).unwrap();
+        assert!(quoter.verify(&signed, SwapMode::Ecash).is_err());
+    }
+
+    #[test]
+    fn fee_eating_the_amount_is_rejected() {
+        assert!(quoter()
+            .quote(
+                federation_id(1),
+                federation_id(2),
+                Amount::from_msats(1_000)
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn lightning_quotes_need_a_listed_pair() {
+        let config = SwapConfig {
+            only_listed_pairs: true,
+            pairs: vec![PairConfig {
+                from_federation_id: federation_id(1),
+                to_federation_id: federation_id(2),
+                fee: None,
+                min_amount_msat: None,
+                max_amount_msat: None,
+            }],
+            ..SwapConfig::default()
+        };
+        let quoter = Quoter::new(config, "password");
+        let amount = Amount::from_sats(100);
+
+        // Whether the federation can be quoted from lightning and to lightning
+        let cases = [(1, (true, true)), (2, (true, true)), (3, (false, false))];
+
+        check_cases(cases, |byte| {
+            let federation = federation_id(byte);
+            (
+                quoter.quote_ln_to_ecash(federation, amount).is_ok(),
+                quoter
+                    .quote_ecash_to_ln(federation, "lnbc1".to_string(), amount, Amount::ZERO)
+                    .is_ok(),
+            )
+        });
+    }
+
+    #[test]
+    fn same_federation_is_rejected() {
+        assert!(quoter()
+            .quote(federation_id(1), federation_id(1), Amount::from_sats(100))
+            .is_err());
+    }
+}
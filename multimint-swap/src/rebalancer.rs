@@ -0,0 +1,599 @@
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::Amount;
+use futures_util::StreamExt;
+use multimint::MultiMint;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::config::RebalanceConfig;
+use crate::db::{RebalanceKey, RebalanceKeyPrefix, RebalanceOutcome, RebalanceRecord};
+use crate::lightning::gateway_fee;
+use crate::liquidity::{Liquidity, ReserveLevel, ReserveStatus};
+use crate::swaps::unix_now;
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// Moves funds from over-funded to under-funded federations over Lightning, logging every rebalance in the multimint database
+#[derive(Debug, Clone)]
+pub struct Rebalancer {
+    multimint: MultiMint,
+    liquidity: Liquidity,
+    config: RebalanceConfig,
+    db: Database,
+}
+
+/// A transfer the rebalancer decided to make
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Plan {
+    from_federation_id: FederationId,
+    to_federation_id: FederationId,
+    amount: Amount,
+}
+
+impl Rebalancer {
+    pub fn new(multimint: MultiMint, liquidity: Liquidity, config: RebalanceConfig) -> Self {
+        let db = multimint.db().clone();
+        Self {
+            multimint,
+            liquidity,
+            config,
+            db,
+        }
+    }
+
+    /// Spawn a background task rebalancing every `interval_secs`, or nothing if the rebalancer is disabled
+    pub fn spawn(&self) -> Option<JoinHandle<()>> {
+        if !self.config.enabled {
+            return None;
+        }
+
+        let rebalancer = self.clone();
+        let interval = Duration::from_secs(self.config.interval_secs);
+        info!(
+            "Rebalancing every {interval:?}{}",
+            if self.config.dry_run { " (dry run)" } else { "" }
+        );
+
+        Some(tokio::spawn(async move {
+            loop {
+                if let Err(e) = rebalancer.rebalance().await {
+                    warn!("Rebalance failed: {e}");
+                }
+                tokio::time::sleep(interval).await;
+            }
+        }))
+    }
+
+    /// Make at most one transfer, from the federation furthest above its target to the one furthest below it, if either crossed a reserve threshold.
+    ///
+    /// A pair whose last rebalances failed is left alone for a while, longer after every failure. In dry-run mode a plan identical to the last logged one is not logged again.
+    pub async fn rebalance(&self) -> Result<Option<(OperationId, RebalanceRecord)>> {
+        let statuses = self.liquidity.statuses().await;
+        self.rebalance_with(
+            &statuses,
+            |plan| async move { self.estimate_fee(&plan).await },
+        )
+        .await
+    }
+
+    /// `rebalance` given the reserve statuses of the federations and how to estimate the fee of a transfer
+    async fn rebalance_with<F, Fut>(
+        &self,
+        statuses: &[ReserveStatus],
+        estimate_fee: F,
+    ) -> Result<Option<(OperationId, RebalanceRecord)>>
+    where
+        F: FnOnce(Plan) -> Fut,
+        Fut: Future<Output = Result<Amount>>,
+    {
+        let Some(plan) = plan(statuses, self.config.max_amount_msat) else {
+            return Ok(None);
+        };
+
+        let pair_records = self
+            .rebalances()
+            .await
+            .into_iter()
+            .map(|(_, record)| record)
+            .filter(|record| {
+                record.from_federation_id == plan.from_federation_id
+                    && record.to_federation_id == plan.to_federation_id
+            })
+            .collect::<Vec<_>>();
+        if let Some(retry_at) = retry_at(pair_records, self.config.interval_secs) {
+            if unix_now() < retry_at {
+                info!(
+                    "Skipping rebalance from {} to {}: the last attempts failed, retrying after {retry_at}",
+                    plan.from_federation_id, plan.to_federation_id
+                );
+                return Ok(None);
+            }
+        }
+
+        let estimated_fee = estimate_fee(plan.clone()).await?;
+        let max_fee = max_fee(plan.amount, self.config.max_fee_ppm);
+        if estimated_fee > max_fee {
+            warn!(
+                "Skipping rebalance of {} from {} to {}: estimated fee {estimated_fee} is above {max_fee}",
+                plan.amount, plan.from_federation_id, plan.to_federation_id
+            );
+            return Ok(None);
+        }
+
+        if let Some(budget_msat) = self.config.daily_fee_budget_msat {
+            let spent = self.fees_since(unix_now().saturating_sub(DAY_SECS)).await;
+            if !within_budget(spent, estimated_fee, budget_msat) {
+                warn!(
+                    "Skipping rebalance of {} from {} to {}: daily fee budget of {} is spent ({spent})",
+                    plan.amount,
+                    plan.from_federation_id,
+                    plan.to_federation_id,
+                    Amount::from_msats(budget_msat)
+                );
+                return Ok(None);
+            }
+        }
+
+        let outcome = if self.config.dry_run {
+            if self.is_last_dry_run(&plan).await {
+                return Ok(None);
+            }
+            info!(
+                "Dry run: would rebalance {} from {} to {} for about {estimated_fee}",
+                plan.amount, plan.from_federation_id, plan.to_federation_id
+            );
+            RebalanceOutcome::DryRun
+        } else {
+            info!(
+                "Rebalancing {} from {} to {}",
+                plan.amount, plan.from_federation_id, plan.to_federation_id
+            );
+            match self
+                .multimint
+                .transfer(&plan.from_federation_id, &plan.to_federation_id, plan.amount)
+                .await
+            {
+                Ok((transfer_id, transfer)) => RebalanceOutcome::Transferred {
+                    transfer_id,
+                    state: transfer.state,
+                    fee: transfer.fee,
+                },
+                Err(e) => RebalanceOutcome::Failed {
+                    error: e.to_string(),
+                },
+            }
+        };
+
+        let record = RebalanceRecord {
+            from_federation_id: plan.from_federation_id,
+            to_federation_id: plan.to_federation_id,
+            amount: plan.amount,
+            estimated_fee,
+            outcome,
+            created_at: unix_now(),
+        };
+        let id = OperationId::new_random();
+        self.save(id, &record).await?;
+
+        Ok(Some((id, record)))
+    }
+
+    /// Whether the most recent rebalance is a dry run of the same plan, so the log doesn't fill up with it while nothing moves
+    async fn is_last_dry_run(&self, plan: &Plan) -> bool {
+        self.rebalances()
+            .await
+            .into_iter()
+            .max_by_key(|(_, record)| record.created_at)
+            .is_some_and(|(_, record)| {
+                record.outcome == RebalanceOutcome::DryRun
+                    && record.from_federation_id == plan.from_federation_id
+                    && record.to_federation_id == plan.to_federation_id
+                    && record.amount == plan.amount
+            })
+    }
+
+    /// The fee charged by the source federation's gateway for paying `amount`
+    async fn estimate_fee(&self, plan: &Plan) -> Result<Amount> {
+        let client = self.multimint.get_or_err(&plan.from_federation_id).await?;
+        gateway_fee(&client, plan.amount)
+            .await
+            .map_err(|e| anyhow!("No gateway for {}: {e}", plan.from_federation_id))
+    }
+
+    async fn fees_since(&self, since: u64) -> Amount {
+        let msats = self
+            .rebalances()
+            .await
+            .iter()
+            .filter(|(_, record)| record.created_at >= since)
+            .map(|(_, record)| record.fee().msats)
+            .sum();
+        Amount::from_msats(msats)
+    }
+
+    pub async fn rebalances(&self) -> Vec<(OperationId, RebalanceRecord)> {
+        self.db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&RebalanceKeyPrefix)
+            .await
+            .map(|(key, record)| (key.id, record))
+            .collect::<Vec<_>>()
+            .await
+    }
+
+    async fn save(&self, id: OperationId, record: &RebalanceRecord) -> Result<()> {
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_entry(&RebalanceKey { id }, record).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| anyhow!("Failed to save rebalance: {:?}", e))
+    }
+}
+
+/// Plan a transfer from the federation furthest above its target to the one furthest below it, if any federation crossed a reserve threshold
+fn plan(statuses: &[ReserveStatus], max_amount_msat: Option<u64>) -> Option<Plan> {
+    if !statuses
+        .iter()
+        .any(|status| status.level != ReserveLevel::Ok)
+    {
+        return None;
+    }
+
+    let surplus = statuses
+        .iter()
+        .filter(|status| status.level != ReserveLevel::Low)
+        .map(|status| {
+            let surplus = status.balance.msats.saturating_sub(status.target.msats);
+            (status.federation_id, surplus)
+        })
+        .max_by_key(|(_, surplus)| *surplus)?;
+    let deficit = statuses
+        .iter()
+        .filter(|status| status.level != ReserveLevel::High)
+        .map(|status| {
+            let deficit = status.target.msats.saturating_sub(status.balance.msats);
+            (status.federation_id, deficit)
+        })
+        .max_by_key(|(_, deficit)| *deficit)?;
+
+    let mut amount_msat = surplus.1.min(deficit.1);
+    if let Some(max_amount_msat) = max_amount_msat {
+        amount_msat = amount_msat.min(max_amount_msat);
+    }
+    if amount_msat == 0 || surplus.0 == deficit.0 {
+        return None;
+    }
+
+    Some(Plan {
+        from_federation_id: surplus.0,
+        to_federation_id: deficit.0,
+        amount: Amount::from_msats(amount_msat),
+    })
+}
+
+/// The largest fee accepted for moving `amount`
+fn max_fee(amount: Amount, max_fee_ppm: u64) -> Amount {
+    Amount::from_msats((u128::from(amount.msats) * u128::from(max_fee_ppm) / 1_000_000) as u64)
+}
+
+/// When a pair may be rebalanced again after failing: the interval doubles with every consecutive failure, up to a day. `None` if its last attempt did not fail
+fn retry_at(mut records: Vec<RebalanceRecord>, interval_secs: u64) -> Option<u64> {
+    // Dry runs neither failed nor succeeded
+    records.retain(|record| record.outcome != RebalanceOutcome::DryRun);
+    records.sort_by_key(|record| std::cmp::Reverse(record.created_at));
+
+    let failures = records
+        .iter()
+        .take_while(|record| record.is_failure())
+        .count() as u32;
+    let last_failure = records.first().filter(|_| failures > 0)?;
+    let backoff = interval_secs
+        .saturating_mul(2u64.saturating_pow(failures))
+        .min(DAY_SECS);
+    Some(last_failure.created_at + backoff)
+}
+
+/// Whether paying `fee` keeps the fees spent over the last 24 hours within the budget
+fn within_budget(spent: Amount, fee: Amount, budget_msat: u64) -> bool {
+    spent.msats + fee.msats <= budget_msat
+}
+
+#[cfg(test)]
+mod tests {
+    use multimint::db::TransferState;
+    use multimint::test_utils::{check_cases, federation_id, multimint};
+
+    use super::*;
+    use crate::config::SwapConfig;
+    use crate::db::RebalanceOutcome;
+
+    const FEE: Amount = Amount::from_msats(10);
+
+    async fn rebalancer(config: RebalanceConfig) -> Rebalancer {
+        let multimint = multimint().await;
+        let liquidity = Liquidity::new(multimint.clone(), SwapConfig::default());
+        Rebalancer::new(multimint, liquidity, config)
+    }
+
+    fn dry_run() -> RebalanceConfig {
+        RebalanceConfig {
+            enabled: true,
+            dry_run: true,
+            ..RebalanceConfig::default()
+        }
+    }
+
+    /// Federation 1 holds 6_000 msat over its target, federation 3 is 3_000 msat short of it
+    fn unbalanced() -> [ReserveStatus; 3] {
+        [
+            status(1, ReserveLevel::High, 10_000, 4_000),
+            status(2, ReserveLevel::Ok, 5_000, 5_000),
+            status(3, ReserveLevel::Low, 1_000, 4_000),
+        ]
+    }
+
+    fn record(outcome: RebalanceOutcome, created_at: u64) -> RebalanceRecord {
+        RebalanceRecord {
+            from_federation_id: federation_id(1),
+            to_federation_id: federation_id(3),
+            amount: Amount::from_msats(3_000),
+            estimated_fee: FEE,
+            outcome,
+            created_at,
+        }
+    }
+
+    fn transferred(state: TransferState, fee: Amount) -> RebalanceOutcome {
+        RebalanceOutcome::Transferred {
+            transfer_id: OperationId([1; 32]),
+            state,
+            fee,
+        }
+    }
+
+    fn failed() -> RebalanceOutcome {
+        RebalanceOutcome::Failed {
+            error: "no route".to_string(),
+        }
+    }
+
+    async fn rebalance(
+        rebalancer: &Rebalancer,
+        statuses: &[ReserveStatus],
+        fee: Amount,
+    ) -> Option<RebalanceRecord> {
+        rebalancer
+            .rebalance_with(statuses, |_| async move { Ok(fee) })
+            .await
+            .unwrap()
+            .map(|(_, record)| record)
+    }
+
+    fn status(byte: u8, level: ReserveLevel, balance_msat: u64, target_msat: u64) -> ReserveStatus {
+        ReserveStatus {
+            federation_id: federation_id(byte),
+            balance: Amount::from_msats(balance_msat),
+            min_reserve: Amount::ZERO,
+            max_reserve: None,
+            target: Amount::from_msats(target_msat),
+            level,
+        }
+    }
+
+    #[tokio::test]
+    async fn dry_run_logs_the_plan_once() {
+        let rebalancer = rebalancer(dry_run()).await;
+
+        let record = rebalance(&rebalancer, &unbalanced(), FEE).await.unwrap();
+        assert_eq!(record.from_federation_id, federation_id(1));
+        assert_eq!(record.to_federation_id, federation_id(3));
+        assert_eq!(record.amount, Amount::from_msats(3_000));
+        assert_eq!(record.estimated_fee, FEE);
+        assert_eq!(record.outcome, RebalanceOutcome::DryRun);
+
+        assert_eq!(rebalance(&rebalancer, &unbalanced(), FEE).await, None);
+        assert_eq!(rebalancer.rebalances().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn dry_run_skips_fees_above_the_cap() {
+        let rebalancer = rebalancer(RebalanceConfig {
+            max_fee_ppm: 10_000,
+            ..dry_run()
+        })
+        .await;
+
+        // 1% of the 3_000 msat planned
+        assert_eq!(
+            rebalance(&rebalancer, &unbalanced(), Amount::from_msats(31)).await,
+            None
+        );
+        assert!(rebalancer.rebalances().await.is_empty());
+        assert!(
+            rebalance(&rebalancer, &unbalanced(), Amount::from_msats(30))
+                .await
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn dry_run_stops_at_the_daily_budget() {
+        let rebalancer = rebalancer(RebalanceConfig {
+            daily_fee_budget_msat: Some(1_000),
+            ..dry_run()
+        })
+        .await;
+        let now = unix_now();
+        let spent = [
+            (Amount::from_msats(500), now - DAY_SECS - 1),
+            (Amount::from_msats(600), now - 60),
+        ];
+        for (fee, created_at) in spent {
+            let outcome = transferred(TransferState::Succeeded, fee);
+            rebalancer
+                .save(OperationId::new_random(), &record(outcome, created_at))
+                .await
+                .unwrap();
+        }
+
+        // Only the fee paid within the last day counts
+        assert!(
+            rebalance(&rebalancer, &unbalanced(), Amount::from_msats(400))
+                .await
+                .is_some()
+        );
+        let records = rebalancer.rebalances().await.len();
+        let next_plan = [
+            status(1, ReserveLevel::High, 10_000, 4_000),
+            status(3, ReserveLevel::Low, 2_000, 4_000),
+        ];
+        assert_eq!(
+            rebalance(&rebalancer, &next_plan, Amount::from_msats(401)).await,
+            None
+        );
+        assert_eq!(rebalancer.rebalances().await.len(), records);
+    }
+
+    #[tokio::test]
+    async fn failing_pair_is_backed_off() {
+        let rebalancer = rebalancer(dry_run()).await;
+        rebalancer
+            .save(OperationId::new_random(), &record(failed(), unix_now()))
+            .await
+            .unwrap();
+        assert_eq!(rebalance(&rebalancer, &unbalanced(), FEE).await, None);
+
+        // A plan between another pair is not held back
+        let other_pair = [
+            status(2, ReserveLevel::High, 10_000, 4_000),
+            status(3, ReserveLevel::Low, 1_000, 4_000),
+        ];
+        assert!(rebalance(&rebalancer, &other_pair, FEE).await.is_some());
+    }
+
+    #[test]
+    fn backoff_doubles_with_consecutive_failures() {
+        let interval = 100;
+        let succeeded = || transferred(TransferState::Succeeded, FEE);
+        let refunded = || {
+            transferred(
+                TransferState::Refunded {
+                    error: "no route".to_string(),
+                },
+                Amount::ZERO,
+            )
+        };
+        let cases = [
+            (vec![], None),
+            (vec![record(succeeded(), 10)], None),
+            (vec![record(RebalanceOutcome::DryRun, 10)], None),
+            (vec![record(failed(), 10)], Some(10 + 200)),
+            (vec![record(refunded(), 10)], Some(10 + 200)),
+            (
+                vec![record(failed(), 10), record(refunded(), 20)],
+                Some(20 + 400),
+            ),
+            // Dry runs between failures don't reset the count
+            (
+                vec![
+                    record(failed(), 10),
+                    record(RebalanceOutcome::DryRun, 15),
+                    record(failed(), 20),
+                ],
+                Some(20 + 400),
+            ),
+            (vec![record(failed(), 10), record(succeeded(), 20)], None),
+            (
+                vec![record(succeeded(), 10), record(failed(), 20)],
+                Some(20 + 200),
+            ),
+            (
+                (0..20).map(|i| record(failed(), i)).collect(),
+                Some(19 + DAY_SECS),
+            ),
+        ];
+
+        check_cases(cases, |records| retry_at(records, interval));
+    }
+
+    #[test]
+    fn moves_surplus_to_deficit() {
+        let statuses = [
+            status(1, ReserveLevel::High, 10_000, 4_000),
+            status(2, ReserveLevel::Ok, 5_000, 5_000),
+            status(3, ReserveLevel::Low, 1_000, 4_000),
+        ];
+
+        assert_eq!(
+            plan(&statuses, None),
+            Some(Plan {
+                from_federation_id: federation_id(1),
+                to_federation_id: federation_id(3),
+                amount: Amount::from_msats(3_000),
+            })
+        );
+        assert_eq!(
+            plan(&statuses, Some(2_000)).map(|plan| plan.amount),
+            Some(Amount::from_msats(2_000))
+        );
+    }
+
+    #[test]
+    fn nothing_to_do_within_bounds() {
+        let statuses = [
+            status(1, ReserveLevel::Ok, 6_000, 4_000),
+            status(2, ReserveLevel::Ok, 2_000, 4_000),
+        ];
+        assert_eq!(plan(&statuses, None), None);
+    }
+
+    #[test]
+    fn single_federation_is_not_rebalanced() {
+        assert_eq!(
+            plan(&[status(1, ReserveLevel::High, 10_000, 4_000)], None),
+            None
+        );
+        assert_eq!(
+            plan(&[status(1, ReserveLevel::Low, 1_000, 4_000)], None),
+            None
+        );
+    }
+
+    #[test]
+    fn same_federation_surplus_and_deficit_is_not_rebalanced() {
+        // The over-funded federation is above its maximum but below its target, the only one with a surplus is the only one that may receive
+        let statuses = [
+            status(1, ReserveLevel::High, 5_000, 6_000),
+            status(2, ReserveLevel::Ok, 2_000, 1_000),
+        ];
+        assert_eq!(plan(&statuses, None), None);
+    }
+
+    #[test]
+    fn fee_above_max_fee_ppm_is_rejected() {
+        let amount = Amount::from_msats(1_000_000);
+        let limit = max_fee(amount, 3_000);
+
+        assert_eq!(limit, Amount::from_msats(3_000));
+        assert!(Amount::from_msats(3_000) <= limit);
+        assert!(Amount::from_msats(3_001) > limit);
+        assert_eq!(max_fee(amount, 0), Amount::ZERO);
+    }
+
+    #[test]
+    fn budget_is_exhausted() {
+        let fee = Amount::from_msats(1_000);
+
+        assert!(within_budget(Amount::ZERO, fee, 50_000));
+        assert!(within_budget(Amount::from_msats(49_000), fee, 50_000));
+        assert!(!within_budget(Amount::from_msats(49_001), fee, 50_000));
+        assert!(!within_budget(Amount::ZERO, fee, 0));
+    }
+}
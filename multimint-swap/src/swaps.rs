@@ -0,0 +1,255 @@
+use anyhow::{anyhow, Result};
+use fedimint_core::core::OperationId;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use futures_util::StreamExt;
+
+use crate::db::{IdempotencyKey, SwapKey, SwapKeyPrefix, SwapRecord, SwapState};
+
+/// Swap records stored in the multimint database
+#[derive(Debug, Clone)]
+pub struct SwapStore {
+    db: Database,
+}
+
+impl SwapStore {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// Record a new swap, failing if the id or the idempotency key was already used by another swap
+    pub async fn create(&self, id: OperationId, record: &SwapRecord) -> Result<()> {
+        let mut dbtx = self.db.begin_transaction().await;
+
+        if dbtx.get_value(&SwapKey { id }).await.is_some() {
+            return Err(anyhow!("Swap {id:?} already exists"));
+        }
+
+        if let Some(key) = &record.idempotency_key {
+            let idempotency_key = IdempotencyKey { key: key.clone() };
+            if dbtx.get_value(&idempotency_key).await.is_some() {
+                return Err(anyhow!("Idempotency key {key} was already used"));
+            }
+            dbtx.insert_new_entry(&idempotency_key, &SwapKey { id })
+                .await;
+        }
+
+        dbtx.insert_new_entry(&SwapKey { id }, record).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| anyhow!("Failed to save swap: {:?}", e))?;
+
+        Ok(())
+    }
+
+    pub async fn get(&self, id: OperationId) -> Option<SwapRecord> {
+        self.db
+            .begin_transaction_nc()
+            .await
+            .get_value(&SwapKey { id })
+            .await
+    }
+
+    /// The swap started with an idempotency key, if any
+    pub async fn get_by_idempotency_key(&self, key: &str) -> Option<(OperationId, SwapRecord)> {
+        let mut dbtx = self.db.begin_transaction_nc().await;
+        let swap_key = dbtx
+            .get_value(&IdempotencyKey {
+                key: key.to_string(),
+            })
+            .await?;
+        let record = dbtx.get_value(&swap_key).await?;
+        Some((swap_key.id, record))
+    }
+
+    pub async fn list(&self) -> Vec<(OperationId, SwapRecord)> {
+        self.db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&SwapKeyPrefix)
+            .await
+            .map(|(key, record)| (key.id, record))
+            .collect::<Vec<_>>()
+            .await
+    }
+
+    /// The swaps whose incoming ecash or Lightning payment was received but whose payout was not settled, left behind when the server stopped
+    pub async fn interrupted(&self) -> Vec<(OperationId, SwapRecord)> {
+        self.list()
+            .await
+            .into_iter()
+            .filter(|(_, record)| {
+                matches!(
+                    record.state,
+                    SwapState::Reissued | SwapState::PayingOut { .. }
+                )
+            })
+            .collect()
+    }
+
+    /// Move a swap to a new state, returning the updated record
+    pub async fn update_state(&self, id: OperationId, state: SwapState) -> Result<SwapRecord> {
+        self.update_state_from(id, None, state).await
+    }
+
+    /// Move a swap to a new state only if it is still in `expected`, so concurrent requests can't both act on it
+    pub async fn compare_and_update_state(
+        &self,
+        id: OperationId,
+        expected: &SwapState,
+        state: SwapState,
+    ) -> Result<SwapRecord> {
+        self.update_state_from(id, Some(expected), state).await
+    }
+
+    async fn update_state_from(
+        &self,
+        id: OperationId,
+        expected: Option<&SwapState>,
+        state: SwapState,
+    ) -> Result<SwapRecord> {
+        let mut dbtx = self.db.begin_transaction().await;
+        let mut record = dbtx
+            .get_value(&SwapKey { id })
+            .await
+            .ok_or_else(|| anyhow!("Unknown swap: {id:?}"))?;
+        if expected.is_some_and(|expected| *expected != record.state) {
+            return Err(anyhow!("Swap {id:?} changed state"));
+        }
+
+        record.state = state;
+        record.updated_at = unix_now();
+        dbtx.insert_entry(&SwapKey { id }, &record).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| anyhow!("Failed to update swap: {:?}", e))?;
+
+        Ok(record)
+    }
+}
+
+pub fn unix_now() -> u64 {
+    fedimint_core::time::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::Amount;
+    use multimint::test_utils::federation_id;
+
+    use super::*;
+    use crate::db::SwapMode;
+
+    fn store() -> SwapStore {
+        SwapStore::new(Database::new(MemDatabase::new(), Default::default()))
+    }
+
+    fn record(mode: SwapMode, state: SwapState) -> SwapRecord {
+        SwapRecord {
+            mode,
+            from_federation_id: federation_id(1),
+            to_federation_id: federation_id(2),
+            amount_in: Amount::from_sats(1_000),
+            amount_out: Amount::from_sats(990),
+            state,
+            invoice: None,
+            idempotency_key: None,
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn swaps_left_paying_out_are_interrupted() {
+        let swaps = store();
+        let seeded = [
+            (1, SwapMode::Ecash, SwapState::Reissued, true),
+            (2, SwapMode::LnToEcash, SwapState::Reissued, true),
+            (3, SwapMode::EcashToLn, SwapState::Reissued, true),
+            (
+                4,
+                SwapMode::EcashToLn,
+                SwapState::PayingOut {
+                    operation_id: Some(OperationId([9; 32])),
+                },
+                true,
+            ),
+            (
+                5,
+                SwapMode::Ecash,
+                SwapState::PayingOut { operation_id: None },
+                true,
+            ),
+            (6, SwapMode::Ecash, SwapState::Pending, false),
+            (
+                7,
+                SwapMode::LnToEcash,
+                SwapState::AwaitingPayment {
+                    invoice: "lnbc1".to_string(),
+                    operation_id: OperationId([8; 32]),
+                },
+                false,
+            ),
+            (
+                8,
+                SwapMode::Ecash,
+                SwapState::Owed {
+                    error: "no liquidity".to_string(),
+                },
+                false,
+            ),
+            (
+                9,
+                SwapMode::Ecash,
+                SwapState::Failed {
+                    error: "invalid ecash".to_string(),
+                },
+                false,
+            ),
+        ];
+        for (id, mode, state, _) in &seeded {
+            swaps
+                .create(OperationId([*id; 32]), &record(*mode, state.clone()))
+                .await
+                .unwrap();
+        }
+
+        let mut interrupted = swaps
+            .interrupted()
+            .await
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        interrupted.sort();
+        let expected = seeded
+            .iter()
+            .filter(|(_, _, _, interrupted)| *interrupted)
+            .map(|(id, ..)| OperationId([*id; 32]))
+            .collect::<Vec<_>>();
+        assert_eq!(interrupted, expected);
+    }
+
+    #[tokio::test]
+    async fn a_reissued_swap_is_paid_out_once() {
+        let swaps = store();
+        let id = OperationId([1; 32]);
+        swaps
+            .create(id, &record(SwapMode::Ecash, SwapState::Reissued))
+            .await
+            .unwrap();
+
+        let paying_out = SwapState::PayingOut { operation_id: None };
+        let record = swaps
+            .compare_and_update_state(id, &SwapState::Reissued, paying_out.clone())
+            .await
+            .unwrap();
+        assert_eq!(record.state, paying_out);
+        assert!(swaps
+            .compare_and_update_state(id, &SwapState::Reissued, paying_out)
+            .await
+            .is_err());
+    }
+}
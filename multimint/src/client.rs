@@ -1,15 +1,20 @@
 //! LocalClientBuilder is a builder pattern for adding Fedimint Clients to the multimint
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::path::PathBuf;
 
+use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitRegistry};
+use fedimint_client::module::{ClientModule, ClientModuleInstance};
 use fedimint_client::secret::{PlainRootSecretStrategy, RootSecretStrategy};
-use fedimint_client::{get_config_from_db, Client, FederationInfo};
+use fedimint_client::{get_config_from_db, Client, ClientArc, FederationInfo};
+use fedimint_core::config::ClientConfig;
+use fedimint_core::core::{ModuleInstanceId, ModuleKind};
 use fedimint_core::db::{
     Committable, Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped,
 };
+use fedimint_core::module::CommonModuleInit;
 use fedimint_ln_client::LightningClientInit;
 use fedimint_mint_client::MintClientInit;
 use fedimint_wallet_client::WalletClientInit;
@@ -18,15 +23,111 @@ use rand::thread_rng;
 use tracing::info;
 
 use crate::db::{FederationConfig, FederationIdKey, FederationIdKeyPrefix};
+use crate::error::MultiMintError;
+
+/// Module kind of the mint module, the default primary module
+pub const MINT_MODULE_KIND: ModuleKind = ModuleKind::from_static_str("mint");
+/// Module kind of the wallet module
+pub const WALLET_MODULE_KIND: ModuleKind = ModuleKind::from_static_str("wallet");
+
+/// Get the first module of type `M` of a client, failing with `MultiMintError::MissingModule` if the federation has none
+pub fn first_module<M: ClientModule>(client: &ClientArc) -> Result<ClientModuleInstance<'_, M>> {
+    let kind = M::kind();
+    if client.get_first_instance(&kind).is_none() {
+        return Err(MultiMintError::MissingModule(kind.to_string()).into());
+    }
+    Ok(client.get_first_module::<M>())
+}
 
 #[derive(Debug, Clone)]
 pub struct LocalClientBuilder {
     work_dir: PathBuf,
+    module_inits: ClientModuleInitRegistry,
+    primary_module_kind: ModuleKind,
+    required_module_kinds: Vec<ModuleKind>,
 }
 
 impl LocalClientBuilder {
+    /// Create a builder registering the Wallet, Mint and Lightning modules, all of which a federation must support, with the mint as primary module
     pub fn new(work_dir: PathBuf) -> Self {
-        Self { work_dir }
+        Self::empty(work_dir)
+            .with_module(WalletClientInit(None))
+            .with_module(MintClientInit)
+            .with_module(LightningClientInit)
+    }
+
+    /// Create a builder without any client modules registered, using the mint as primary module
+    pub fn empty(work_dir: PathBuf) -> Self {
+        Self {
+            work_dir,
+            module_inits: ClientModuleInitRegistry::new(),
+            primary_module_kind: MINT_MODULE_KIND,
+            required_module_kinds: Vec::new(),
+        }
+    }
+
+    /// Register a client module that federations must support
+    pub fn with_module<I: ClientModuleInit>(mut self, module_init: I) -> Self {
+        self.module_inits.attach(module_init);
+        self.require_module(<I::Common as CommonModuleInit>::KIND)
+    }
+
+    /// Register a client module that is used when a federation supports it, but not required to join
+    pub fn with_optional_module(mut self, module_init: impl ClientModuleInit) -> Self {
+        self.module_inits.attach(module_init);
+        self
+    }
+
+    /// Fail to build clients for federations that lack a module of the given kind
+    pub fn require_module(mut self, kind: ModuleKind) -> Self {
+        if !self.required_module_kinds.contains(&kind) {
+            self.required_module_kinds.push(kind);
+        }
+        self
+    }
+
+    /// Use the first module of the given kind in each federation as the primary module
+    pub fn with_primary_module_kind(mut self, kind: ModuleKind) -> Self {
+        self.primary_module_kind = kind;
+        self
+    }
+
+    /// The top level directory the client databases are stored in
+    pub fn work_dir(&self) -> &PathBuf {
+        &self.work_dir
+    }
+
+    /// Check the federation supports every required module and find the instance id of the primary module
+    pub fn primary_module_instance(&self, config: &ClientConfig) -> Result<ModuleInstanceId> {
+        self.find_primary_module(
+            config
+                .modules
+                .iter()
+                .map(|(instance_id, module)| (*instance_id, module.kind())),
+        )
+    }
+
+    fn find_primary_module<'a>(
+        &self,
+        modules: impl Iterator<Item = (ModuleInstanceId, &'a ModuleKind)> + Clone,
+    ) -> Result<ModuleInstanceId> {
+        let has_kind = |kind: &ModuleKind| {
+            modules
+                .clone()
+                .any(|(_, module_kind)| module_kind == kind)
+        };
+
+        if let Some(missing) = self.required_module_kinds.iter().find(|kind| !has_kind(kind)) {
+            return Err(MultiMintError::MissingModule(missing.to_string()).into());
+        }
+
+        modules
+            .clone()
+            .find(|(_, kind)| **kind == self.primary_module_kind)
+            .map(|(instance_id, _)| instance_id)
+            .ok_or_else(|| {
+                MultiMintError::MissingModule(self.primary_module_kind.to_string()).into()
+            })
     }
 }
 
@@ -45,10 +146,21 @@ impl LocalClientBuilder {
 
         let mut client_builder = Client::builder();
         client_builder.with_database(db.clone());
-        client_builder.with_module(WalletClientInit(None));
-        client_builder.with_module(MintClientInit);
-        client_builder.with_module(LightningClientInit);
-        client_builder.with_primary_module(1);
+        client_builder.with_module_inits(self.module_inits.clone());
+
+        let client_config = match get_config_from_db(&db).await {
+            Some(client_config) => client_config,
+            None => {
+                let federation_info = FederationInfo::from_invite_code(config.invite_code).await?;
+                let client_config = federation_info.config().clone();
+                client_builder.with_federation_info(federation_info);
+                client_config
+            }
+        };
+        let primary_module_instance = self
+            .primary_module_instance(&client_config)
+            .with_context(|| format!("Cannot build client for federation {federation_id}"))?;
+        client_builder.with_primary_module(primary_module_instance);
 
         let client_secret = match client_builder.load_decodable_client_secret().await {
             Ok(secret) => secret,
@@ -68,11 +180,6 @@ impl LocalClientBuilder {
 
         let root_secret = PlainRootSecretStrategy::to_root_secret(&client_secret);
 
-        if get_config_from_db(&db).await.is_none() {
-            let federation_info = FederationInfo::from_invite_code(config.invite_code).await?;
-            client_builder.with_federation_info(federation_info);
-        };
-
         let client_res = client_builder.build(root_secret.clone()).await?;
 
         Ok(client_res)
@@ -101,3 +208,59 @@ impl LocalClientBuilder {
             .collect::<Vec<_>>()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LN_KIND: ModuleKind = ModuleKind::from_static_str("ln");
+
+    fn missing_module(result: Result<ModuleInstanceId>) -> String {
+        match MultiMintError::find(&result.unwrap_err()) {
+            Some(MultiMintError::MissingModule(kind)) => kind.clone(),
+            other => panic!("expected a missing module error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn finds_primary_module_when_all_required_modules_are_present() {
+        let modules = [(0, WALLET_MODULE_KIND), (1, MINT_MODULE_KIND), (2, LN_KIND)];
+        let builder = LocalClientBuilder::new(PathBuf::new());
+
+        let primary = builder.find_primary_module(modules.iter().map(|(id, kind)| (*id, kind)));
+        assert_eq!(primary.unwrap(), 1);
+    }
+
+    #[test]
+    fn rejects_federation_missing_a_registered_module() {
+        let modules = [(0, WALLET_MODULE_KIND), (1, MINT_MODULE_KIND)];
+        let builder = LocalClientBuilder::new(PathBuf::new());
+
+        let primary = builder.find_primary_module(modules.iter().map(|(id, kind)| (*id, kind)));
+        assert_eq!(missing_module(primary), "ln");
+    }
+
+    #[test]
+    fn optional_modules_are_not_required() {
+        let modules = [(0, MINT_MODULE_KIND)];
+        let builder = LocalClientBuilder::empty(PathBuf::new())
+            .with_module(MintClientInit)
+            .with_optional_module(LightningClientInit);
+
+        let primary = builder.find_primary_module(modules.iter().map(|(id, kind)| (*id, kind)));
+        assert_eq!(primary.unwrap(), 0);
+    }
+
+    #[test]
+    fn uses_configured_primary_module_kind() {
+        let modules = [(0, MINT_MODULE_KIND), (1, LN_KIND)];
+        let builder = LocalClientBuilder::empty(PathBuf::new()).with_primary_module_kind(LN_KIND);
+        let primary = builder.find_primary_module(modules.iter().map(|(id, kind)| (*id, kind)));
+        assert_eq!(primary.unwrap(), 1);
+
+        let builder =
+            LocalClientBuilder::empty(PathBuf::new()).with_primary_module_kind(WALLET_MODULE_KIND);
+        let primary = builder.find_primary_module(modules.iter().map(|(id, kind)| (*id, kind)));
+        assert_eq!(missing_module(primary), "wallet");
+    }
+}
@@ -1,13 +1,22 @@
+use std::collections::BTreeMap;
+
 use fedimint_core::api::InviteCode;
 use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::{impl_db_lookup, impl_db_record};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount, PeerId};
 use serde::{Deserialize, Serialize};
 
+/// Key prefixes of the records in the multimint database.
+///
+/// Prefixes from `0x20` up are left to applications storing their own records through `MultiMint::db`.
 #[repr(u8)]
 #[derive(Clone, Debug)]
 pub enum DbKeyPrefix {
     FederationConfig = 0x04,
+    OnchainOperation = 0x05,
+    Transfer = 0x06,
+    FederationMeta = 0x07,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -36,3 +45,130 @@ impl_db_record!(
 );
 
 impl_db_lookup!(key = FederationIdKey, query_prefix = FederationIdKeyPrefix);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct OnchainOperationKey {
+    pub operation_id: OperationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct OnchainOperationKeyPrefix;
+
+/// Direction of an on-chain operation tracked by the multimint
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnchainDirection {
+    Deposit,
+    Withdraw,
+}
+
+/// A peg-in or peg-out started through the multimint, so it can be looked up by operation id alone
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct OnchainOperation {
+    pub federation_id: FederationId,
+    pub direction: OnchainDirection,
+    pub address: String,
+    /// Amount sent on-chain, only known up front for withdrawals
+    pub amount_sat: Option<u64>,
+    /// Estimated on-chain fee paid for withdrawals
+    pub fee_sat: Option<u64>,
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+}
+
+impl_db_record!(
+    key = OnchainOperationKey,
+    value = OnchainOperation,
+    db_prefix = DbKeyPrefix::OnchainOperation,
+);
+
+impl_db_lookup!(
+    key = OnchainOperationKey,
+    query_prefix = OnchainOperationKeyPrefix
+);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct TransferKey {
+    pub id: OperationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct TransferKeyPrefix;
+
+/// State of a Lightning transfer between two federations
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "state")]
+pub enum TransferState {
+    /// The invoice was created on the destination federation and is being paid from the source federation
+    Pending,
+    /// The destination federation claimed the payment
+    Succeeded,
+    /// The payment failed and the source federation is waiting for its funds to be refunded
+    AwaitingRefund { error: String },
+    /// The payment failed and the funds were refunded to the source federation
+    Refunded { error: String },
+    /// The transfer failed without funds leaving the source federation, or in a state the multimint cannot recover from
+    Failed { error: String },
+}
+
+impl TransferState {
+    /// Whether the transfer has reached a state it will not leave again
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            TransferState::Succeeded | TransferState::Refunded { .. } | TransferState::Failed { .. }
+        )
+    }
+}
+
+/// A transfer of funds between two federations, made by paying an invoice of the destination federation from the source federation
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct TransferRecord {
+    pub from_federation_id: FederationId,
+    pub to_federation_id: FederationId,
+    pub amount: Amount,
+    /// Lightning fee paid by the source federation on top of `amount`
+    pub fee: Amount,
+    pub invoice: String,
+    pub receive_operation_id: OperationId,
+    pub pay_operation_id: Option<OperationId>,
+    pub state: TransferState,
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+}
+
+impl_db_record!(
+    key = TransferKey,
+    value = TransferRecord,
+    db_prefix = DbKeyPrefix::Transfer,
+);
+
+impl_db_lookup!(key = TransferKey, query_prefix = TransferKeyPrefix);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct FederationMetaKey {
+    pub id: FederationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct FederationMetaKeyPrefix;
+
+/// The latest federation metadata and guardian endpoints fetched from the guardians
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct FederationMeta {
+    pub meta: BTreeMap<String, String>,
+    pub api_endpoints: BTreeMap<PeerId, String>,
+    /// Unix timestamp in seconds
+    pub fetched_at: u64,
+}
+
+impl_db_record!(
+    key = FederationMetaKey,
+    value = FederationMeta,
+    db_prefix = DbKeyPrefix::FederationMeta,
+);
+
+impl_db_lookup!(
+    key = FederationMetaKey,
+    query_prefix = FederationMetaKeyPrefix
+);
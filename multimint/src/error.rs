@@ -0,0 +1,113 @@
+//! Errors returned by the multimint
+//!
+//! Methods return `anyhow::Result`, failing with a `MultiMintError` where the caller may want to react to the cause. Find it with `MultiMintError::find`.
+
+use std::fmt;
+
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::Amount;
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone)]
+pub enum MultiMintError {
+    /// The multimint has no client for the federation
+    FederationNotFound(FederationId),
+    /// The operation was not started through the multimint
+    OperationNotFound(OperationId),
+    /// The manual secret is not 64 hex encoded bytes
+    InvalidSecret(String),
+    /// The federation lacks a module the client builder requires
+    MissingModule(String),
+    /// Source and destination federation are the same
+    SameFederation,
+    InsufficientBalance {
+        federation_id: FederationId,
+        needed: Amount,
+        available: Amount,
+    },
+    /// A bitcoin address belongs to another network than the federation
+    WrongNetwork { address: String, network: String },
+    /// The guardians of a federation did not respond in time
+    Timeout(FederationId),
+}
+
+impl MultiMintError {
+    /// Find the `MultiMintError` that caused an error, if any
+    pub fn find(error: &anyhow::Error) -> Option<&MultiMintError> {
+        error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<MultiMintError>())
+    }
+
+    /// A stable, machine-readable code for the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            MultiMintError::FederationNotFound(_) => "federation_not_found",
+            MultiMintError::OperationNotFound(_) => "operation_not_found",
+            MultiMintError::InvalidSecret(_) => "invalid_secret",
+            MultiMintError::MissingModule(_) => "missing_module",
+            MultiMintError::SameFederation => "same_federation",
+            MultiMintError::InsufficientBalance { .. } => "insufficient_balance",
+            MultiMintError::WrongNetwork { .. } => "wrong_network",
+            MultiMintError::Timeout(_) => "federation_timeout",
+        }
+    }
+
+    /// Structured details about the error, for the errors that have any
+    pub fn details(&self) -> Option<Value> {
+        match self {
+            MultiMintError::InsufficientBalance {
+                federation_id,
+                needed,
+                available,
+            } => Some(json!({
+                "federation_id": federation_id,
+                "needed_msat": needed,
+                "available_msat": available,
+            })),
+            MultiMintError::WrongNetwork { address, network } => Some(json!({
+                "address": address,
+                "network": network,
+            })),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for MultiMintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultiMintError::FederationNotFound(federation_id) => {
+                write!(f, "No client for federation: {federation_id}")
+            }
+            MultiMintError::OperationNotFound(operation_id) => {
+                write!(f, "Unknown operation: {operation_id:?}")
+            }
+            MultiMintError::InvalidSecret(reason) => write!(f, "Invalid manual secret: {reason}"),
+            MultiMintError::MissingModule(kind) => {
+                write!(f, "Federation does not support the required {kind} module")
+            }
+            MultiMintError::SameFederation => {
+                write!(f, "Source and destination federation are the same")
+            }
+            MultiMintError::InsufficientBalance {
+                federation_id,
+                needed,
+                available,
+            } => write!(
+                f,
+                "Not enough ecash in federation {federation_id}: needed {needed}, available {available}"
+            ),
+            MultiMintError::WrongNetwork { address, network } => write!(
+                f,
+                "Address {address} is not valid for the federation's network: {network}"
+            ),
+            MultiMintError::Timeout(federation_id) => {
+                write!(f, "Timed out waiting for the guardians of federation: {federation_id}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MultiMintError {}
@@ -0,0 +1,332 @@
+//! Events emitted by the multimint
+//!
+//! Subscribe with `MultiMint::subscribe_events`. Events are broadcast to every subscriber; a subscriber that falls behind by more than `EVENT_CHANNEL_CAPACITY` events skips the oldest ones.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use fedimint_client::oplog::{ChronologicalOperationLogKey, OperationLogEntry};
+use fedimint_client::ClientArc;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::{Amount, PeerId};
+use fedimint_ln_client::{
+    LightningClientModule, LightningOperationMeta, LightningOperationMetaVariant,
+};
+use fedimint_mint_client::{MintClientModule, MintOperationMeta, MintOperationMetaVariant};
+use fedimint_wallet_client::{WalletClientModule, WalletOperationMeta, WalletOperationMetaVariant};
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use crate::client::first_module;
+use crate::error::MultiMintError;
+use crate::MultiMint;
+
+/// Number of events buffered for slow subscribers
+pub const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Number of operations the operation watcher reads from an operation log at a time
+const OPERATION_PAGE_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum MultiMintEvent {
+    /// A client for a new federation was added to the multimint
+    FederationJoined { federation_id: FederationId },
+    /// A federation was left with `MultiMint::leave`
+    FederationLeft {
+        federation_id: FederationId,
+        final_balance: Amount,
+    },
+    /// The ecash balance of a client changed
+    BalanceChanged {
+        federation_id: FederationId,
+        balance: Amount,
+    },
+    /// An operation reached a new state, as reported by the update stream of the module that started it
+    OperationUpdated {
+        federation_id: FederationId,
+        operation_id: OperationId,
+        operation_kind: String,
+        state: serde_json::Value,
+    },
+    /// A federation meta field was added, changed or removed
+    MetaChanged {
+        federation_id: FederationId,
+        key: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// A federation announced it will shut down at `expiry_timestamp`, optionally pointing to a successor federation
+    ShutdownAnnounced {
+        federation_id: FederationId,
+        expiry_timestamp: Option<u64>,
+        successor: Option<String>,
+    },
+    /// The guardians of a federation changed their API endpoints
+    GuardianEndpointsChanged {
+        federation_id: FederationId,
+        old: BTreeMap<PeerId, String>,
+        new: BTreeMap<PeerId, String>,
+    },
+}
+
+impl MultiMintEvent {
+    /// The event type, as found in the `type` field of its JSON representation
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MultiMintEvent::FederationJoined { .. } => "federation_joined",
+            MultiMintEvent::FederationLeft { .. } => "federation_left",
+            MultiMintEvent::BalanceChanged { .. } => "balance_changed",
+            MultiMintEvent::OperationUpdated { .. } => "operation_updated",
+            MultiMintEvent::MetaChanged { .. } => "meta_changed",
+            MultiMintEvent::ShutdownAnnounced { .. } => "shutdown_announced",
+            MultiMintEvent::GuardianEndpointsChanged { .. } => "guardian_endpoints_changed",
+        }
+    }
+
+    pub fn federation_id(&self) -> FederationId {
+        match self {
+            MultiMintEvent::FederationJoined { federation_id }
+            | MultiMintEvent::FederationLeft { federation_id, .. }
+            | MultiMintEvent::BalanceChanged { federation_id, .. }
+            | MultiMintEvent::OperationUpdated { federation_id, .. }
+            | MultiMintEvent::MetaChanged { federation_id, .. }
+            | MultiMintEvent::ShutdownAnnounced { federation_id, .. }
+            | MultiMintEvent::GuardianEndpointsChanged { federation_id, .. } => *federation_id,
+        }
+    }
+}
+
+impl MultiMint {
+    /// Forward the balance changes of a client as events until its federation is left.
+    pub(crate) fn watch_balance(&self, federation_id: FederationId, client: ClientArc) {
+        let multimint = self.clone();
+
+        tokio::spawn(async move {
+            let balances = client.subscribe_balance_changes().await;
+            drop(client);
+
+            multimint
+                .forward_until_left(federation_id, balances, |balance| {
+                    MultiMintEvent::BalanceChanged {
+                        federation_id,
+                        balance,
+                    }
+                })
+                .await;
+        });
+    }
+
+    /// Forward every state an operation goes through as an event, until it finished or its federation is left.
+    fn watch_operation(
+        &self,
+        federation_id: FederationId,
+        client: ClientArc,
+        operation_id: OperationId,
+        operation_kind: String,
+    ) {
+        let multimint = self.clone();
+
+        tokio::spawn(async move {
+            let updates = match subscribe_operation(&client, operation_id, &operation_kind).await {
+                Ok(updates) => updates,
+                Err(e) => {
+                    debug!(
+                        "Not watching operation {operation_id:?} of federation {federation_id}: {e}"
+                    );
+                    return;
+                }
+            };
+            drop(client);
+
+            multimint
+                .forward_until_left(federation_id, updates, |state| {
+                    MultiMintEvent::OperationUpdated {
+                        federation_id,
+                        operation_id,
+                        operation_kind: operation_kind.clone(),
+                        state,
+                    }
+                })
+                .await;
+        });
+    }
+
+    /// Emit an event for every item of `updates` until the stream ends or the federation is left
+    async fn forward_until_left<T>(
+        &self,
+        federation_id: FederationId,
+        mut updates: BoxStream<'static, T>,
+        to_event: impl Fn(T) -> MultiMintEvent,
+    ) {
+        let mut events = self.subscribe_events();
+
+        loop {
+            tokio::select! {
+                update = updates.next() => {
+                    let Some(update) = update else {
+                        break;
+                    };
+                    self.emit(to_event(update));
+                }
+                event = events.recv() => match event {
+                    Ok(MultiMintEvent::FederationLeft { federation_id: left, .. })
+                        if left == federation_id => break,
+                    Ok(_) => {}
+                    // The `FederationLeft` event may be among the skipped ones
+                    Err(RecvError::Lagged(_)) => {
+                        if !self.has(&federation_id).await {
+                            break;
+                        }
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
+    }
+
+    /// Spawn a background task checking the operation logs of all the clients each `interval` for new operations and following each of them through its module's update stream, emitting an event for every state it reaches.
+    ///
+    /// Of the operations that already exist when a client's log is first read, only the ones still running are followed.
+    pub fn spawn_operation_watcher(&self, interval: Duration) -> JoinHandle<()> {
+        let multimint = self.clone();
+
+        tokio::spawn(async move {
+            // The newest operation of each client that is already watched, `None` for a client whose log was empty
+            let mut newest: BTreeMap<FederationId, Option<ChronologicalOperationLogKey>> =
+                BTreeMap::new();
+            let mut ticker = tokio::time::interval(interval);
+
+            loop {
+                ticker.tick().await;
+                let clients = multimint.clients.lock().await.clone();
+                newest.retain(|federation_id, _| clients.contains_key(federation_id));
+
+                for (federation_id, client) in clients {
+                    let first_read = !newest.contains_key(&federation_id);
+                    let seen = newest.get(&federation_id).cloned().flatten();
+                    let operations = operations_since(&client, seen.as_ref()).await;
+                    if let Some((key, _)) = operations.first() {
+                        newest.insert(federation_id, Some(key.clone()));
+                    } else {
+                        newest.entry(federation_id).or_insert(None);
+                    }
+
+                    for (key, entry) in operations {
+                        if first_read && entry.outcome::<serde_json::Value>().is_some() {
+                            continue;
+                        }
+
+                        multimint.watch_operation(
+                            federation_id,
+                            client.clone(),
+                            key.operation_id,
+                            entry.operation_module_kind().to_string(),
+                        );
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// The operations of a client that are newer than `seen`, newest first, or all of them without `seen`
+async fn operations_since(
+    client: &ClientArc,
+    seen: Option<&ChronologicalOperationLogKey>,
+) -> Vec<(ChronologicalOperationLogKey, OperationLogEntry)> {
+    let mut operations = Vec::new();
+    let mut start_after = None;
+
+    loop {
+        let page = client
+            .operation_log()
+            .list_operations(OPERATION_PAGE_SIZE, start_after)
+            .await;
+        let last_page = page.len() < OPERATION_PAGE_SIZE;
+
+        for (key, entry) in page {
+            if seen.map_or(false, |seen| seen.operation_id == key.operation_id) {
+                return operations;
+            }
+            start_after = Some(key.clone());
+            operations.push((key, entry));
+        }
+
+        if last_page {
+            return operations;
+        }
+    }
+}
+
+/// Subscribe to the update stream of an operation in the module that started it, serializing each state
+async fn subscribe_operation(
+    client: &ClientArc,
+    operation_id: OperationId,
+    operation_kind: &str,
+) -> anyhow::Result<BoxStream<'static, serde_json::Value>> {
+    let entry = client
+        .operation_log()
+        .get_operation(operation_id)
+        .await
+        .ok_or(MultiMintError::OperationNotFound(operation_id))?;
+
+    match operation_kind {
+        "ln" => {
+            let lightning = first_module::<LightningClientModule>(client)?;
+            let variant = entry.meta::<LightningOperationMeta>().variant;
+            if let LightningOperationMetaVariant::Receive { .. } = variant {
+                let updates = lightning.subscribe_ln_receive(operation_id).await?;
+                Ok(serialized(updates.into_stream()))
+            } else if let LightningOperationMetaVariant::Pay(pay) = variant {
+                if pay.is_internal_payment {
+                    let updates = lightning.subscribe_internal_pay(operation_id).await?;
+                    Ok(serialized(updates.into_stream()))
+                } else {
+                    let updates = lightning.subscribe_ln_pay(operation_id).await?;
+                    Ok(serialized(updates.into_stream()))
+                }
+            } else {
+                Err(anyhow!("Lightning operation has no update stream"))
+            }
+        }
+        "mint" => {
+            let mint = first_module::<MintClientModule>(client)?;
+            let variant = entry.meta::<MintOperationMeta>().variant;
+            if let MintOperationMetaVariant::Reissuance { .. } = variant {
+                let updates = mint.subscribe_reissue_external_notes(operation_id).await?;
+                Ok(serialized(updates.into_stream()))
+            } else {
+                let updates = mint.subscribe_spend_notes(operation_id).await?;
+                Ok(serialized(updates.into_stream()))
+            }
+        }
+        "wallet" => {
+            let wallet = first_module::<WalletClientModule>(client)?;
+            let variant = entry.meta::<WalletOperationMeta>().variant;
+            if let WalletOperationMetaVariant::Deposit { .. } = variant {
+                let updates = wallet.subscribe_deposit_updates(operation_id).await?;
+                Ok(serialized(updates.into_stream()))
+            } else {
+                let updates = wallet.subscribe_withdraw_updates(operation_id).await?;
+                Ok(serialized(updates.into_stream()))
+            }
+        }
+        kind => Err(anyhow!("Operations of the {kind} module have no update stream")),
+    }
+}
+
+/// Serialize each state of an update stream, so the streams of all modules can be handled alike
+fn serialized<T: Serialize + Send + 'static>(
+    updates: BoxStream<'static, T>,
+) -> BoxStream<'static, serde_json::Value> {
+    updates
+        .map(|update| serde_json::to_value(update).unwrap_or(serde_json::Value::Null))
+        .boxed()
+}
@@ -53,27 +53,41 @@
 
 
 use anyhow::Result;
-use fedimint_client::ClientArc;
+use fedimint_client::oplog::OperationLogEntry;
+use fedimint_client::{ClientArc, FederationInfo};
 use fedimint_core::api::InviteCode;
-use fedimint_core::config::{FederationId, FederationIdPrefix, JsonClientConfig};
-use fedimint_core::db::Database;
+use fedimint_core::config::{ClientConfig, FederationId, FederationIdPrefix, JsonClientConfig};
+use fedimint_core::core::OperationId;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
 use fedimint_core::Amount;
 use fedimint_mint_client::MintClientModule;
-use fedimint_wallet_client::WalletClientModule;
+use fedimint_wallet_client::config::WalletClientConfig;
 use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tracing::warn;
-use types::InfoResponse;
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex};
+use tracing::{info, warn};
+use types::{GuardianInfo, InfoResponse, PreviewResponse, ReconcileReport};
 
 pub mod client;
 pub mod db;
+pub mod error;
+pub mod events;
+pub mod meta;
+pub mod onchain;
+pub mod transfer;
 pub mod types;
 
-use crate::client::LocalClientBuilder;
-use crate::db::FederationConfig;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+
+use crate::client::{first_module, LocalClientBuilder, WALLET_MODULE_KIND};
+use crate::db::{FederationConfig, FederationIdKey, FederationMetaKey};
+use crate::error::MultiMintError;
+use crate::events::{MultiMintEvent, EVENT_CHANNEL_CAPACITY};
+use crate::meta::META_FEDERATION_NAME;
 
 /// `MultiMint` is a struct for managing Fedimint Clients across multiple federations.
 #[derive(Debug, Clone)]
@@ -81,6 +95,7 @@ pub struct MultiMint {
     db: Database,
     pub client_builder: LocalClientBuilder,
     pub clients: Arc<Mutex<BTreeMap<FederationId, ClientArc>>>,
+    events: broadcast::Sender<MultiMintEvent>,
 }
 
 impl MultiMint {
@@ -121,22 +136,51 @@ impl MultiMint {
     /// }
     /// ```
     pub async fn new(work_dir: PathBuf) -> Result<Self> {
+        Self::with_client_builder(LocalClientBuilder::new(work_dir)).await
+    }
+
+    /// Create a new `MultiMint` instance that builds its clients with a custom `LocalClientBuilder`, e.g. to register other client modules.
+    ///
+    /// The top level directory is the builder's work directory.
+    pub async fn with_client_builder(client_builder: LocalClientBuilder) -> Result<Self> {
         let db = Database::new(
-            fedimint_rocksdb::RocksDb::open(work_dir.join("multimint.db"))?,
+            fedimint_rocksdb::RocksDb::open(client_builder.work_dir().join("multimint.db"))?,
             Default::default(),
         );
 
-        let client_builder = LocalClientBuilder::new(work_dir);
-
         let clients = Arc::new(Mutex::new(BTreeMap::new()));
 
         Self::load_clients(&mut clients.clone(), &db, &client_builder).await?;
 
-        Ok(Self {
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+
+        let multimint = Self {
             db: db,
             client_builder: client_builder,
             clients,
-        })
+            events,
+        };
+
+        for (federation_id, client) in multimint.clients.lock().await.iter() {
+            multimint.watch_balance(*federation_id, client.clone());
+        }
+
+        Ok(multimint)
+    }
+
+    /// The top level multimint database, for applications storing their own records next to the multimint's.
+    pub fn db(&self) -> &Database {
+        &self.db
+    }
+
+    /// Subscribe to the events emitted by the multimint.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<MultiMintEvent> {
+        self.events.subscribe()
+    }
+
+    /// Broadcast an event to all subscribers, if there are any.
+    pub(crate) fn emit(&self, event: MultiMintEvent) {
+        let _ = self.events.send(event);
     }
 
     /// Load the clients from from the top level database in the work directory
@@ -171,8 +215,11 @@ impl MultiMint {
     pub async fn register_new(&mut self, invite_code: InviteCode, manual_secret: Option<String>) -> Result<FederationId> {
         let manual_secret: Option<[u8; 64]> = match manual_secret {
             Some(manual_secret) => {
-                let bytes = hex::decode(&manual_secret)?;
-                Some(bytes.try_into().map_err(|_| anyhow::anyhow!("Manual secret must be 64 bytes long"))?)
+                let bytes = hex::decode(&manual_secret)
+                    .map_err(|e| MultiMintError::InvalidSecret(e.to_string()))?;
+                Some(bytes.try_into().map_err(|_| {
+                    MultiMintError::InvalidSecret("Manual secret must be 64 bytes long".to_string())
+                })?)
             },
             None => None,
         };
@@ -195,16 +242,126 @@ impl MultiMint {
 
         let client = self.client_builder.build(client_cfg.clone(), manual_secret).await?;
 
-        self.clients.lock().await.insert(federation_id, client);
+        self.clients.lock().await.insert(federation_id, client.clone());
 
         let dbtx = self.db.begin_transaction().await;
         self.client_builder
             .save_config(client_cfg.clone(), dbtx)
             .await?;
 
+        self.watch_balance(federation_id, client);
+        self.emit(MultiMintEvent::FederationJoined { federation_id });
+
         Ok(federation_id)
     }
 
+    /// Bring the joined federations in line with a list of invite codes: join the listed federations the multimint has no client for and, if `leave_unlisted` is set, leave the ones that are not listed and hold no ecash.
+    ///
+    /// Failing to join or leave a federation does not stop the others from being reconciled, the failures are collected in the report.
+    pub async fn reconcile(
+        &mut self,
+        invite_codes: &[InviteCode],
+        leave_unlisted: bool,
+    ) -> Result<ReconcileReport> {
+        let mut report = ReconcileReport::default();
+
+        for invite_code in invite_codes {
+            let federation_id = invite_code.federation_id();
+            if self.has(&federation_id).await {
+                report.unchanged.push(federation_id);
+                continue;
+            }
+
+            match self.register_new(invite_code.clone(), None).await {
+                Ok(_) => report.joined.push(federation_id),
+                Err(e) => {
+                    warn!("Failed to join federation {federation_id}: {e}");
+                    report.failed.insert(federation_id, e.to_string());
+                }
+            }
+        }
+
+        let listed = invite_codes
+            .iter()
+            .map(InviteCode::federation_id)
+            .collect::<Vec<_>>();
+        for federation_id in self.ids().await {
+            if listed.contains(&federation_id) {
+                continue;
+            }
+
+            if !leave_unlisted {
+                report.unlisted.push(federation_id);
+                continue;
+            }
+
+            // Federations holding ecash are only left with an explicit `leave`, so a forgotten invite code can't hide funds
+            let Some(client) = self.get(&federation_id).await else {
+                report.failed.insert(
+                    federation_id,
+                    MultiMintError::FederationNotFound(federation_id).to_string(),
+                );
+                continue;
+            };
+            let balance = client.get_balance().await;
+            drop(client);
+            if balance > Amount::ZERO {
+                warn!("Not leaving unlisted federation {federation_id}, it still holds {balance}");
+                report.kept_with_balance.insert(federation_id, balance);
+                continue;
+            }
+
+            match self.leave(&federation_id).await {
+                Ok(balance) => {
+                    report.left.insert(federation_id, balance);
+                }
+                Err(e) => {
+                    warn!("Failed to leave federation {federation_id}: {e}");
+                    report.failed.insert(federation_id, e.to_string());
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Preview a federation by fetching its config from the guardians, without joining it or writing anything to disk.
+    ///
+    /// Fails if the guardians don't respond within `timeout`.
+    pub async fn preview(&self, invite_code: InviteCode, timeout: Duration) -> Result<PreviewResponse> {
+        let federation_id = invite_code.federation_id();
+        let federation_info = tokio::time::timeout(timeout, FederationInfo::from_invite_code(invite_code))
+            .await
+            .map_err(|_| MultiMintError::Timeout(federation_id))??;
+        let config = federation_info.config();
+
+        Ok(PreviewResponse {
+            federation_id,
+            federation_name: config.global.meta.get(META_FEDERATION_NAME).cloned(),
+            guardians: config
+                .global
+                .api_endpoints
+                .iter()
+                .map(|(peer_id, peer_url)| {
+                    (
+                        *peer_id,
+                        GuardianInfo {
+                            name: peer_url.name.clone(),
+                            url: peer_url.url.to_string(),
+                        },
+                    )
+                })
+                .collect(),
+            network: wallet_network(config),
+            modules: config
+                .modules
+                .iter()
+                .map(|(instance_id, module)| (*instance_id, module.kind().to_string()))
+                .collect(),
+            meta: config.global.meta.clone(),
+        })
+    }
+
     /// Get all the clients in the multimint.
     pub async fn all(&self) -> Vec<ClientArc> {
         self.clients.lock().await.values().cloned().collect()
@@ -220,6 +377,13 @@ impl MultiMint {
         self.clients.lock().await.get(federation_id).cloned()
     }
 
+    /// Get a client by its federation id, erroring if the multimint has no client for it.
+    pub async fn get_or_err(&self, federation_id: &FederationId) -> Result<ClientArc> {
+        self.get(federation_id)
+            .await
+            .ok_or_else(|| MultiMintError::FederationNotFound(*federation_id).into())
+    }
+
     /// Get a client by its federation id as a string. (Useful for passing in from the command line or typescript/python/golang sdks)
     pub async fn get_by_str(&self, federation_id_str: &str) -> Option<ClientArc> {
         let federation_id = FederationId::from_str(federation_id_str).ok()?;
@@ -248,6 +412,22 @@ impl MultiMint {
         }
     }
 
+    /// Find the federation an operation belongs to by searching the operation logs of all the clients.
+    pub async fn find_operation(
+        &self,
+        operation_id: OperationId,
+    ) -> Option<(FederationId, OperationLogEntry)> {
+        let clients = self.clients.lock().await.clone();
+
+        for (federation_id, client) in clients {
+            if let Some(entry) = client.operation_log().get_operation(operation_id).await {
+                return Some((federation_id, entry));
+            }
+        }
+
+        None
+    }
+
     /// Update a client by its federation id.
     pub async fn update(&self, federation_id: &FederationId, new_client: ClientArc) {
         self.clients
@@ -257,10 +437,44 @@ impl MultiMint {
     }
 
     /// Remove a client by its federation id.
+    ///
+    /// The client is loaded again on restart, use `leave` to remove it for good.
     pub async fn remove(&self, federation_id: &FederationId) {
         self.clients.lock().await.remove(federation_id);
     }
 
+    /// Leave a federation: remove its client and delete its config from the multimint database, so it is not loaded again on restart.
+    ///
+    /// The client is shut down once its last handle is dropped: the multimint drops its own here and its watchers drop theirs on the `FederationLeft` event. The client's own database stays in the work directory, so joining the federation again later restores its ecash. Returns the balance the client held when it was removed.
+    pub async fn leave(&self, federation_id: &FederationId) -> Result<Amount> {
+        let client = self
+            .clients
+            .lock()
+            .await
+            .remove(federation_id)
+            .ok_or(MultiMintError::FederationNotFound(*federation_id))?;
+        let balance = client.get_balance().await;
+        // Stops the client's background tasks unless a request still holds a handle, in which case they stop when it completes
+        drop(client);
+
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.remove_entry(&FederationIdKey { id: *federation_id })
+            .await;
+        dbtx.remove_entry(&FederationMetaKey { id: *federation_id })
+            .await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to remove federation config: {:?}", e))?;
+
+        info!("Left federation {federation_id} with a balance of {balance}");
+        self.emit(MultiMintEvent::FederationLeft {
+            federation_id: *federation_id,
+            final_balance: balance,
+        });
+
+        Ok(balance)
+    }
+
     /// Check if a client exists by its federation id.
     pub async fn has(&self, federation_id: &FederationId) -> bool {
         self.clients.lock().await.contains_key(federation_id)
@@ -303,35 +517,124 @@ impl MultiMint {
     }
 
     /// Get the info for all the clients in the multimint.
+    ///
+    /// Federations whose info can't be read, like ones without a mint module, are left out.
     pub async fn info(&self) -> Result<BTreeMap<FederationId, InfoResponse>> {
         let mut info_map = BTreeMap::new();
         let clients = self.clients.lock().await;
 
         for (federation_id, client) in clients.iter() {
-            let mint_client = client.get_first_module::<MintClientModule>();
-            let wallet_client = client.get_first_module::<WalletClientModule>();
-            let summary = mint_client
-                .get_wallet_summary(
-                    &mut self
-                        .db
-                        .begin_transaction_nc()
-                        .await
-                        .to_ref_with_prefix_module_id(1),
-                )
-                .await;
-
-            let info = InfoResponse {
-                federation_id: federation_id.clone(),
-                network: wallet_client.get_network().to_string(),
-                meta: client.get_config().global.meta.clone(),
-                total_amount_msat: summary.total_amount(),
-                total_num_notes: summary.count_items(),
-                denominations_msat: summary,
-            };
-
-            info_map.insert(federation_id.clone(), info);
+            match self.client_info(federation_id, client).await {
+                Ok(info) => {
+                    info_map.insert(federation_id.clone(), info);
+                }
+                Err(e) => warn!("Skipping info for federation {federation_id}: {e}"),
+            }
         }
 
         Ok(info_map)
     }
+
+    /// Get the info for a single client by its federation id.
+    pub async fn info_by_id(&self, federation_id: &FederationId) -> Result<InfoResponse> {
+        let client = self.get_or_err(federation_id).await?;
+        self.client_info(federation_id, &client).await
+    }
+
+    async fn client_info(
+        &self,
+        federation_id: &FederationId,
+        client: &ClientArc,
+    ) -> Result<InfoResponse> {
+        let mint_client = first_module::<MintClientModule>(client)?;
+        let summary = mint_client
+            .get_wallet_summary(&mut mint_client.db.begin_transaction_nc().await)
+            .await;
+
+        Ok(InfoResponse {
+            federation_id: federation_id.clone(),
+            network: wallet_network(client.get_config()),
+            meta: self
+                .db
+                .begin_transaction_nc()
+                .await
+                .get_value(&FederationMetaKey { id: *federation_id })
+                .await
+                .map(|federation_meta| federation_meta.meta)
+                .unwrap_or_else(|| client.get_config().global.meta.clone()),
+            total_amount_msat: summary.total_amount(),
+            total_num_notes: summary.count_items(),
+            denominations_msat: summary,
+        })
+    }
+}
+
+/// Bitcoin network of the federation's wallet module, if it has one
+fn wallet_network(config: &ClientConfig) -> Option<String> {
+    config
+        .get_first_module_by_kind::<WalletClientConfig>(WALLET_MODULE_KIND)
+        .ok()
+        .map(|(_, wallet_config)| wallet_config.network.to_string())
+}
+
+/// Current unix time in seconds, used to timestamp records in the multimint database
+pub(crate) fn unix_now() -> u64 {
+    fedimint_core::time::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::util::SafeUrl;
+    use fedimint_core::PeerId;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::test_utils::{federation_id, multimint};
+
+    #[tokio::test]
+    async fn preview_of_silent_guardians_times_out() {
+        let multimint = multimint().await;
+        // Accepts connections without ever answering
+        let guardian = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = SafeUrl::parse(&format!("ws://{}", guardian.local_addr().unwrap())).unwrap();
+        let invite_code = InviteCode::new(url, PeerId::from(0), federation_id(1));
+
+        let error = multimint
+            .preview(invite_code, Duration::from_millis(200))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            MultiMintError::find(&error).unwrap().code(),
+            "federation_timeout"
+        );
+        assert!(multimint.ids().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unknown_federation_has_no_info() {
+        let multimint = multimint().await;
+        let error = multimint.info_by_id(&federation_id(1)).await.unwrap_err();
+        assert_eq!(
+            MultiMintError::find(&error).unwrap().code(),
+            "federation_not_found"
+        );
+        assert!(multimint.info().await.unwrap().is_empty());
+        assert!(multimint.ecash_balances().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn leaving_an_unknown_federation_fails_without_an_event() {
+        let multimint = multimint().await;
+        let mut events = multimint.subscribe_events();
+
+        let error = multimint.leave(&federation_id(1)).await.unwrap_err();
+        assert_eq!(
+            MultiMintError::find(&error).unwrap().code(),
+            "federation_not_found"
+        );
+        assert!(events.try_recv().is_err());
+    }
 }
@@ -0,0 +1,343 @@
+//! Refreshing federation metadata
+//!
+//! A client only downloads the federation config once, when it joins. The multimint periodically fetches it again from the guardians, stores the latest metadata in its database and emits a `MultiMintEvent` for every change.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use fedimint_client::FederationInfo;
+use fedimint_core::config::{ClientConfig, FederationId};
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::Amount;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::db::{FederationIdKey, FederationMeta, FederationMetaKey};
+use crate::error::MultiMintError;
+use crate::events::MultiMintEvent;
+use crate::{unix_now, MultiMint};
+
+/// Meta key holding the federation's name
+pub const META_FEDERATION_NAME: &str = "federation_name";
+/// Meta key holding a URL to the federation's icon
+pub const META_FEDERATION_ICON_URL: &str = "federation_icon_url";
+/// Meta key holding the maximum balance in msats users should keep in the federation
+pub const META_MAX_BALANCE_MSATS: &str = "max_balance_msats";
+/// Meta key holding an announcement the guardians want shown to users
+pub const META_WELCOME_MESSAGE: &str = "welcome_message";
+/// Meta key holding the unix timestamp at which the federation shuts down
+pub const META_FEDERATION_EXPIRY_TIMESTAMP: &str = "federation_expiry_timestamp";
+/// Meta key holding the invite code of the federation users should move to before shutdown
+pub const META_FEDERATION_SUCCESSOR: &str = "federation_successor";
+
+impl FederationMeta {
+    fn from_config(config: &ClientConfig) -> Self {
+        Self {
+            meta: config.global.meta.clone(),
+            api_endpoints: config
+                .global
+                .api_endpoints
+                .iter()
+                .map(|(peer_id, peer_url)| (*peer_id, peer_url.url.to_string()))
+                .collect(),
+            fetched_at: unix_now(),
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.meta.get(META_FEDERATION_NAME).map(String::as_str)
+    }
+
+    pub fn icon_url(&self) -> Option<&str> {
+        self.meta.get(META_FEDERATION_ICON_URL).map(String::as_str)
+    }
+
+    pub fn max_balance(&self) -> Option<Amount> {
+        self.meta
+            .get(META_MAX_BALANCE_MSATS)
+            .and_then(|msats| msats.parse().ok())
+            .map(Amount::from_msats)
+    }
+
+    pub fn announcement(&self) -> Option<&str> {
+        self.meta.get(META_WELCOME_MESSAGE).map(String::as_str)
+    }
+
+    /// Unix timestamp at which the federation announced it shuts down
+    pub fn expiry_timestamp(&self) -> Option<u64> {
+        self.meta
+            .get(META_FEDERATION_EXPIRY_TIMESTAMP)
+            .and_then(|timestamp| timestamp.parse().ok())
+    }
+
+    pub fn successor(&self) -> Option<&str> {
+        self.meta.get(META_FEDERATION_SUCCESSOR).map(String::as_str)
+    }
+
+    fn announces_shutdown(&self) -> bool {
+        self.expiry_timestamp().is_some() || self.successor().is_some()
+    }
+}
+
+impl MultiMint {
+    /// Get the latest metadata of a federation.
+    ///
+    /// Falls back to the metadata in the client config if it was never refreshed.
+    pub async fn federation_meta(&self, federation_id: &FederationId) -> Option<FederationMeta> {
+        let stored = self
+            .db
+            .begin_transaction_nc()
+            .await
+            .get_value(&FederationMetaKey { id: *federation_id })
+            .await;
+
+        match stored {
+            Some(meta) => Some(meta),
+            None => self
+                .get(federation_id)
+                .await
+                .map(|client| FederationMeta::from_config(client.get_config())),
+        }
+    }
+
+    /// Fetch the federation config from the guardians and record what changed in its metadata, emitting an event for every change.
+    pub async fn refresh_meta(&self, federation_id: &FederationId) -> Result<FederationMeta> {
+        let federation_config = self
+            .db
+            .begin_transaction_nc()
+            .await
+            .get_value(&FederationIdKey { id: *federation_id })
+            .await
+            .ok_or(MultiMintError::FederationNotFound(*federation_id))?;
+
+        let federation_info = FederationInfo::from_invite_code(federation_config.invite_code).await?;
+        let new = FederationMeta::from_config(federation_info.config());
+
+        if let Some(old) = self.federation_meta(federation_id).await {
+            self.emit_meta_changes(*federation_id, &old, &new);
+        }
+
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_entry(&FederationMetaKey { id: *federation_id }, &new)
+            .await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| anyhow!("Failed to save federation meta: {:?}", e))?;
+
+        Ok(new)
+    }
+
+    /// Refresh the metadata of every federation in the multimint, logging the ones that could not be reached.
+    pub async fn refresh_all_meta(&self) -> BTreeMap<FederationId, FederationMeta> {
+        let mut metas = BTreeMap::new();
+
+        for federation_id in self.ids().await {
+            match self.refresh_meta(&federation_id).await {
+                Ok(meta) => {
+                    metas.insert(federation_id, meta);
+                }
+                Err(e) => warn!("Failed to refresh meta for federation {federation_id}: {e}"),
+            }
+        }
+
+        metas
+    }
+
+    /// Spawn a background task refreshing the metadata of every federation each `interval`.
+    pub fn spawn_meta_refresh(&self, interval: Duration) -> JoinHandle<()> {
+        let multimint = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let refreshed = multimint.refresh_all_meta().await;
+                info!("Refreshed meta for {} federations", refreshed.len());
+            }
+        })
+    }
+
+    fn emit_meta_changes(
+        &self,
+        federation_id: FederationId,
+        old: &FederationMeta,
+        new: &FederationMeta,
+    ) {
+        for event in meta_changes(federation_id, old, new) {
+            match &event {
+                MultiMintEvent::ShutdownAnnounced {
+                    expiry_timestamp,
+                    successor,
+                    ..
+                } => warn!(
+                    "Federation {federation_id} announced shutdown at {expiry_timestamp:?}, successor: {successor:?}"
+                ),
+                MultiMintEvent::GuardianEndpointsChanged { .. } => {
+                    warn!("Federation {federation_id} changed its guardian endpoints")
+                }
+                _ => {}
+            }
+            self.emit(event);
+        }
+    }
+}
+
+/// The events describing how the metadata of a federation changed between two fetches
+fn meta_changes(
+    federation_id: FederationId,
+    old: &FederationMeta,
+    new: &FederationMeta,
+) -> Vec<MultiMintEvent> {
+    let mut events = Vec::new();
+
+    let keys = old
+        .meta
+        .keys()
+        .chain(new.meta.keys())
+        .collect::<BTreeSet<_>>();
+
+    for key in keys {
+        let (old_value, new_value) = (old.meta.get(key), new.meta.get(key));
+        if old_value != new_value {
+            events.push(MultiMintEvent::MetaChanged {
+                federation_id,
+                key: key.clone(),
+                old: old_value.cloned(),
+                new: new_value.cloned(),
+            });
+        }
+    }
+
+    let shutdown_changed =
+        old.expiry_timestamp() != new.expiry_timestamp() || old.successor() != new.successor();
+    if new.announces_shutdown() && shutdown_changed {
+        events.push(MultiMintEvent::ShutdownAnnounced {
+            federation_id,
+            expiry_timestamp: new.expiry_timestamp(),
+            successor: new.successor().map(str::to_string),
+        });
+    }
+
+    if old.api_endpoints != new.api_endpoints {
+        events.push(MultiMintEvent::GuardianEndpointsChanged {
+            federation_id,
+            old: old.api_endpoints.clone(),
+            new: new.api_endpoints.clone(),
+        });
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::PeerId;
+
+    use super::*;
+    use crate::test_utils::federation_id;
+
+    fn federation_meta(meta: &[(&str, &str)], endpoints: &[(u16, &str)]) -> FederationMeta {
+        FederationMeta {
+            meta: meta
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            api_endpoints: endpoints
+                .iter()
+                .map(|(peer_id, url)| (PeerId::from(*peer_id), url.to_string()))
+                .collect(),
+            fetched_at: 0,
+        }
+    }
+
+    #[test]
+    fn unchanged_meta_emits_nothing() {
+        let meta = federation_meta(&[(META_FEDERATION_NAME, "Fed")], &[(0, "wss://a")]);
+        assert!(meta_changes(federation_id(1), &meta, &meta.clone()).is_empty());
+    }
+
+    #[test]
+    fn added_changed_and_removed_keys_are_reported() {
+        let old = federation_meta(
+            &[(META_FEDERATION_NAME, "Fed"), (META_WELCOME_MESSAGE, "Hi")],
+            &[],
+        );
+        let new = federation_meta(
+            &[(META_FEDERATION_NAME, "New Fed"), (META_MAX_BALANCE_MSATS, "1000")],
+            &[],
+        );
+
+        let changes = meta_changes(federation_id(1), &old, &new)
+            .into_iter()
+            .map(|event| match event {
+                MultiMintEvent::MetaChanged { key, old, new, .. } => (key, old, new),
+                other => panic!("unexpected event {other:?}"),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    META_FEDERATION_NAME.to_string(),
+                    Some("Fed".to_string()),
+                    Some("New Fed".to_string())
+                ),
+                (META_MAX_BALANCE_MSATS.to_string(), None, Some("1000".to_string())),
+                (META_WELCOME_MESSAGE.to_string(), Some("Hi".to_string()), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn shutdown_is_announced_once() {
+        let old = federation_meta(&[], &[]);
+        let announced = federation_meta(
+            &[
+                (META_FEDERATION_EXPIRY_TIMESTAMP, "1700000000"),
+                (META_FEDERATION_SUCCESSOR, "fed11successor"),
+            ],
+            &[],
+        );
+
+        let events = meta_changes(federation_id(1), &old, &announced);
+        assert!(events.iter().any(|event| matches!(
+            event,
+            MultiMintEvent::ShutdownAnnounced {
+                expiry_timestamp: Some(1_700_000_000),
+                successor: Some(successor),
+                ..
+            } if successor == "fed11successor"
+        )));
+
+        // Refetching the same announcement is not a new one
+        let events = meta_changes(federation_id(1), &announced, &announced.clone());
+        assert!(events.is_empty());
+
+        // Withdrawing the announcement only changes the meta keys
+        let events = meta_changes(federation_id(1), &announced, &old);
+        assert!(events
+            .iter()
+            .all(|event| matches!(event, MultiMintEvent::MetaChanged { .. })));
+    }
+
+    #[test]
+    fn changed_guardian_endpoints_are_reported() {
+        let old = federation_meta(&[], &[(0, "wss://a"), (1, "wss://b")]);
+        let new = federation_meta(&[], &[(0, "wss://a"), (1, "wss://c")]);
+
+        match meta_changes(federation_id(1), &old, &new).as_slice() {
+            [MultiMintEvent::GuardianEndpointsChanged {
+                federation_id: changed,
+                old: old_endpoints,
+                new: new_endpoints,
+            }] => {
+                assert_eq!(*changed, federation_id(1));
+                assert_eq!(old_endpoints, &old.api_endpoints);
+                assert_eq!(new_endpoints, &new.api_endpoints);
+            }
+            other => panic!("unexpected events {other:?}"),
+        }
+    }
+}
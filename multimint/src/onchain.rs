@@ -0,0 +1,306 @@
+//! On-chain deposits (peg-ins) and withdrawals (peg-outs) for the clients in the multimint
+//!
+//! Every operation started here is also recorded in the multimint database, so its state can be looked up by operation id without knowing which federation it belongs to.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use bitcoin::{Address, Txid};
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::time::now;
+use fedimint_wallet_client::{DepositState, PegOutFees, WalletClientModule, WithdrawState};
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use tracing::info;
+
+use crate::client::first_module;
+use crate::db::{
+    OnchainDirection, OnchainOperation, OnchainOperationKey, OnchainOperationKeyPrefix,
+};
+use crate::error::MultiMintError;
+use crate::{unix_now, MultiMint};
+
+impl MultiMint {
+    /// Generate a peg-in address for the given federation.
+    ///
+    /// The federation watches the address until `valid_for` has elapsed and claims the deposit as ecash once it has enough confirmations.
+    pub async fn deposit_address(
+        &self,
+        federation_id: &FederationId,
+        valid_for: Duration,
+    ) -> Result<(OperationId, Address)> {
+        let client = self.get_or_err(federation_id).await?;
+        let wallet_client = first_module::<WalletClientModule>(&client)?;
+
+        let (operation_id, address) = wallet_client
+            .get_deposit_address(now() + valid_for, ())
+            .await?;
+
+        self.save_onchain_operation(
+            operation_id,
+            OnchainOperation {
+                federation_id: *federation_id,
+                direction: OnchainDirection::Deposit,
+                address: address.to_string(),
+                amount_sat: None,
+                fee_sat: None,
+                created_at: unix_now(),
+            },
+        )
+        .await?;
+
+        info!("Created deposit address {address} for federation: {federation_id}");
+
+        Ok((operation_id, address))
+    }
+
+    /// Subscribe to the state updates of a deposit created with `deposit_address`.
+    pub async fn subscribe_deposit(
+        &self,
+        operation_id: OperationId,
+    ) -> Result<BoxStream<'static, DepositState>> {
+        let operation = self
+            .get_onchain_operation(operation_id, OnchainDirection::Deposit)
+            .await?;
+        let client = self.get_or_err(&operation.federation_id).await?;
+        let wallet_client = first_module::<WalletClientModule>(&client)?;
+
+        Ok(wallet_client
+            .subscribe_deposit_updates(operation_id)
+            .await?
+            .into_stream())
+    }
+
+    /// Wait until a deposit has been confirmed on-chain and claimed as ecash.
+    pub async fn await_deposit(&self, operation_id: OperationId) -> Result<()> {
+        let mut updates = self.subscribe_deposit(operation_id).await?;
+
+        while let Some(update) = updates.next().await {
+            match update {
+                DepositState::Claimed(_) => return Ok(()),
+                DepositState::Failed(e) => return Err(anyhow!("Deposit failed: {e}")),
+                _ => {}
+            }
+        }
+
+        Err(anyhow!("Deposit update stream ended before the deposit was claimed"))
+    }
+
+    /// Estimate the on-chain fees for withdrawing `amount` to `address` from the given federation.
+    ///
+    /// The returned fees are passed back to `withdraw`, which fails if the federation's fee rate has moved in the meantime.
+    pub async fn withdraw_fees(
+        &self,
+        federation_id: &FederationId,
+        address: Address,
+        amount: bitcoin::Amount,
+    ) -> Result<PegOutFees> {
+        let client = self.get_or_err(federation_id).await?;
+        let wallet_client = first_module::<WalletClientModule>(&client)?;
+        check_network(&address, wallet_client.get_network())?;
+
+        wallet_client.get_withdraw_fees(address, amount).await
+    }
+
+    /// Peg out `amount` to a bitcoin address from the given federation, paying the fees estimated by `withdraw_fees`.
+    pub async fn withdraw(
+        &self,
+        federation_id: &FederationId,
+        address: Address,
+        amount: bitcoin::Amount,
+        fees: PegOutFees,
+    ) -> Result<OperationId> {
+        let client = self.get_or_err(federation_id).await?;
+        let wallet_client = first_module::<WalletClientModule>(&client)?;
+        check_network(&address, wallet_client.get_network())?;
+
+        let fee_sat = fees.amount().to_sat();
+        let operation_id = wallet_client
+            .withdraw(address.clone(), amount, fees, ())
+            .await?;
+
+        self.save_onchain_operation(
+            operation_id,
+            OnchainOperation {
+                federation_id: *federation_id,
+                direction: OnchainDirection::Withdraw,
+                address: address.to_string(),
+                amount_sat: Some(amount.to_sat()),
+                fee_sat: Some(fee_sat),
+                created_at: unix_now(),
+            },
+        )
+        .await?;
+
+        info!("Started withdrawal of {amount} to {address} from federation: {federation_id}");
+
+        Ok(operation_id)
+    }
+
+    /// Subscribe to the state updates of a withdrawal started with `withdraw`.
+    pub async fn subscribe_withdraw(
+        &self,
+        operation_id: OperationId,
+    ) -> Result<BoxStream<'static, WithdrawState>> {
+        let operation = self
+            .get_onchain_operation(operation_id, OnchainDirection::Withdraw)
+            .await?;
+        let client = self.get_or_err(&operation.federation_id).await?;
+        let wallet_client = first_module::<WalletClientModule>(&client)?;
+
+        Ok(wallet_client
+            .subscribe_withdraw_updates(operation_id)
+            .await?
+            .into_stream())
+    }
+
+    /// Wait until the federation has broadcast the peg-out transaction and return its txid.
+    pub async fn await_withdraw(&self, operation_id: OperationId) -> Result<Txid> {
+        let mut updates = self.subscribe_withdraw(operation_id).await?;
+
+        while let Some(update) = updates.next().await {
+            match update {
+                WithdrawState::Succeeded(txid) => return Ok(txid),
+                WithdrawState::Failed(e) => return Err(anyhow!("Withdrawal failed: {e}")),
+                _ => {}
+            }
+        }
+
+        Err(anyhow!("Withdraw update stream ended before the withdrawal succeeded"))
+    }
+
+    /// Get an on-chain operation started through the multimint by its operation id.
+    pub async fn onchain_operation(
+        &self,
+        operation_id: OperationId,
+    ) -> Option<OnchainOperation> {
+        self.db
+            .begin_transaction_nc()
+            .await
+            .get_value(&OnchainOperationKey { operation_id })
+            .await
+    }
+
+    /// List all the on-chain operations started through the multimint.
+    pub async fn onchain_operations(&self) -> Vec<(OperationId, OnchainOperation)> {
+        self.db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&OnchainOperationKeyPrefix)
+            .await
+            .map(|(key, operation)| (key.operation_id, operation))
+            .collect::<Vec<_>>()
+            .await
+    }
+
+    async fn get_onchain_operation(
+        &self,
+        operation_id: OperationId,
+        direction: OnchainDirection,
+    ) -> Result<OnchainOperation> {
+        match self.onchain_operation(operation_id).await {
+            Some(operation) if operation.direction == direction => Ok(operation),
+            Some(_) => Err(anyhow!(
+                "Operation {operation_id:?} is not a {direction:?} operation"
+            )),
+            None => Err(MultiMintError::OperationNotFound(operation_id).into()),
+        }
+    }
+
+    async fn save_onchain_operation(
+        &self,
+        operation_id: OperationId,
+        operation: OnchainOperation,
+    ) -> Result<()> {
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_entry(&OnchainOperationKey { operation_id }, &operation)
+            .await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| anyhow!("Failed to save on-chain operation: {:?}", e))
+    }
+}
+
+/// Make sure a bitcoin address belongs to the network the federation runs on.
+pub fn check_network(address: &Address, network: bitcoin::Network) -> Result<()> {
+    if !address.is_valid_for_network(network) {
+        return Err(MultiMintError::WrongNetwork {
+            address: address.to_string(),
+            network: network.to_string(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bitcoin::Network;
+
+    use super::*;
+    use crate::test_utils::{check_cases, federation_id, multimint, save_onchain_operation};
+
+    #[test]
+    fn address_of_another_network_is_rejected() {
+        let mainnet = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+        let testnet = "mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn";
+        let wrong_network = Some("wrong_network");
+        let cases = [
+            ((mainnet, Network::Bitcoin), None),
+            ((mainnet, Network::Testnet), wrong_network),
+            ((mainnet, Network::Regtest), wrong_network),
+            ((testnet, Network::Testnet), None),
+            ((testnet, Network::Regtest), None),
+            ((testnet, Network::Bitcoin), wrong_network),
+        ];
+
+        check_cases(cases, |(address, network)| {
+            check_network(&Address::from_str(address).unwrap(), network)
+                .err()
+                .map(|e| MultiMintError::find(&e).unwrap().code())
+        });
+    }
+
+    #[tokio::test]
+    async fn operations_are_looked_up_by_id_and_direction() {
+        let multimint = multimint().await;
+        let deposit_id = OperationId([1; 32]);
+        let deposit = save_onchain_operation(
+            &multimint,
+            deposit_id,
+            federation_id(1),
+            OnchainDirection::Deposit,
+        )
+        .await;
+
+        assert_eq!(
+            multimint.onchain_operation(deposit_id).await,
+            Some(deposit.clone())
+        );
+        assert_eq!(
+            multimint.onchain_operations().await,
+            vec![(deposit_id, deposit)]
+        );
+        assert!(multimint
+            .get_onchain_operation(deposit_id, OnchainDirection::Deposit)
+            .await
+            .is_ok());
+        assert!(multimint
+            .get_onchain_operation(deposit_id, OnchainDirection::Withdraw)
+            .await
+            .is_err());
+
+        let unknown = multimint
+            .get_onchain_operation(OperationId([2; 32]), OnchainDirection::Deposit)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            MultiMintError::find(&unknown).unwrap().code(),
+            "operation_not_found"
+        );
+    }
+}
@@ -0,0 +1,56 @@
+//! Fixtures shared by the tests of the multimint, and of the crates built on it through the `test-utils` feature
+
+use std::fmt::Debug;
+use std::str::FromStr;
+
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+
+use crate::db::{OnchainDirection, OnchainOperation, OnchainOperationKey};
+use crate::MultiMint;
+
+/// A federation id made of `byte` repeated
+pub fn federation_id(byte: u8) -> FederationId {
+    FederationId::from_str(&format!("{byte:02x}").repeat(32)).unwrap()
+}
+
+/// A multimint without any federation, in a fresh directory
+pub async fn multimint() -> MultiMint {
+    let work_dir =
+        std::env::temp_dir().join(format!("multimint-test-{:016x}", rand::random::<u64>()));
+    MultiMint::new(work_dir).await.unwrap()
+}
+
+/// Record an on-chain operation as if the multimint had started it
+pub async fn save_onchain_operation(
+    multimint: &MultiMint,
+    operation_id: OperationId,
+    federation_id: FederationId,
+    direction: OnchainDirection,
+) -> OnchainOperation {
+    let operation = OnchainOperation {
+        federation_id,
+        direction,
+        address: "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq".to_string(),
+        amount_sat: None,
+        fee_sat: None,
+        created_at: 0,
+    };
+    let mut dbtx = multimint.db().begin_transaction().await;
+    dbtx.insert_entry(&OnchainOperationKey { operation_id }, &operation)
+        .await;
+    dbtx.commit_tx_result().await.unwrap();
+    operation
+}
+
+/// Assert that `check` maps the input of every `(input, expected)` case to the expected output, naming the input of a failing case
+pub fn check_cases<I: Debug, O: Debug + PartialEq>(
+    cases: impl IntoIterator<Item = (I, O)>,
+    check: impl Fn(I) -> O,
+) {
+    for (input, expected) in cases {
+        let description = format!("{input:?}");
+        assert_eq!(check(input), expected, "{description}");
+    }
+}
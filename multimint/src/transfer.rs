@@ -0,0 +1,328 @@
+//! Moving funds between federations over Lightning
+//!
+//! A transfer creates an invoice on the destination federation and pays it from the source federation, each through its own gateway. Both Lightning operations are tracked together as a single `TransferRecord` in the multimint database.
+
+use anyhow::{anyhow, Result};
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+use fedimint_core::Amount;
+use fedimint_ln_client::{
+    InternalPayState, LightningClientModule, LnPayState, LnReceiveState, OutgoingLightningPayment,
+    PayType,
+};
+use futures_util::StreamExt;
+use tracing::{info, warn};
+
+use crate::client::first_module;
+use crate::db::{TransferKey, TransferKeyPrefix, TransferRecord, TransferState};
+use crate::error::MultiMintError;
+use crate::{unix_now, MultiMint};
+
+impl MultiMint {
+    /// Transfer `amount` from one federation to another over Lightning.
+    ///
+    /// Resolves once the transfer reached a final state and returns its id along with the record. A failed payment is not an error: the record's state tells whether the funds were refunded to the source federation.
+    pub async fn transfer(
+        &self,
+        from: &FederationId,
+        to: &FederationId,
+        amount: Amount,
+    ) -> Result<(OperationId, TransferRecord)> {
+        if from == to {
+            return Err(MultiMintError::SameFederation.into());
+        }
+
+        let from_client = self.get_or_err(from).await?;
+        let to_client = self.get_or_err(to).await?;
+
+        let from_lightning = first_module::<LightningClientModule>(&from_client)?;
+        let gateway = from_lightning.select_active_gateway().await?;
+        let proportional_fee =
+            u128::from(amount.msats) * u128::from(gateway.fees.proportional_millionths) / 1_000_000;
+        let gateway_fee =
+            Amount::from_msats(u64::from(gateway.fees.base_msat) + proportional_fee as u64);
+
+        // The gateway fee is paid on top of the amount
+        let needed = amount + gateway_fee;
+        let available = from_client.get_balance().await;
+        if available < needed {
+            return Err(MultiMintError::InsufficientBalance {
+                federation_id: *from,
+                needed,
+                available,
+            }
+            .into());
+        }
+
+        let to_lightning = first_module::<LightningClientModule>(&to_client)?;
+        to_lightning.select_active_gateway().await?;
+        let (receive_operation_id, invoice) = to_lightning
+            .create_bolt11_invoice(amount, format!("multimint transfer from {from}"), None, ())
+            .await?;
+
+        let id = OperationId::new_random();
+        let mut record = TransferRecord {
+            from_federation_id: *from,
+            to_federation_id: *to,
+            amount,
+            fee: Amount::ZERO,
+            invoice: invoice.to_string(),
+            receive_operation_id,
+            pay_operation_id: None,
+            state: TransferState::Pending,
+            created_at: unix_now(),
+        };
+        self.save_transfer(id, &record).await?;
+
+        let OutgoingLightningPayment {
+            payment_type, fee, ..
+        } = match from_lightning.pay_bolt11_invoice(invoice, ()).await {
+            Ok(payment) => payment,
+            Err(e) => {
+                record.state = TransferState::Failed {
+                    error: e.to_string(),
+                };
+                self.save_transfer(id, &record).await?;
+                return Ok((id, record));
+            }
+        };
+
+        record.fee = fee;
+        record.pay_operation_id = Some(payment_type.operation_id());
+        self.save_transfer(id, &record).await?;
+
+        info!("Transferring {amount} from federation {from} to {to}, transfer id: {id:?}");
+
+        let mut paid = false;
+        match payment_type {
+            PayType::Lightning(operation_id) => {
+                let mut pay_updates = from_lightning
+                    .subscribe_ln_pay(operation_id)
+                    .await?
+                    .into_stream();
+
+                while let Some(update) = pay_updates.next().await {
+                    record.state = match update {
+                        LnPayState::Success { .. } => {
+                            paid = true;
+                            break;
+                        }
+                        LnPayState::WaitingForRefund { gateway_error, .. } => {
+                            TransferState::AwaitingRefund {
+                                error: format!("{gateway_error:?}"),
+                            }
+                        }
+                        LnPayState::Refunded { gateway_error } => TransferState::Refunded {
+                            error: format!("{gateway_error:?}"),
+                        },
+                        LnPayState::Canceled => TransferState::Failed {
+                            error: "Payment was canceled".to_string(),
+                        },
+                        LnPayState::UnexpectedError { error_message } => TransferState::Failed {
+                            error: error_message,
+                        },
+                        _ => continue,
+                    };
+                    self.save_transfer(id, &record).await?;
+                    if record.state.is_final() {
+                        break;
+                    }
+                }
+            }
+            // Settled by the source federation itself, without going over Lightning
+            PayType::Internal(operation_id) => {
+                let mut pay_updates = from_lightning
+                    .subscribe_internal_pay(operation_id)
+                    .await?
+                    .into_stream();
+
+                while let Some(update) = pay_updates.next().await {
+                    record.state = match update {
+                        InternalPayState::Preimage(_) => {
+                            paid = true;
+                            break;
+                        }
+                        InternalPayState::Funding => continue,
+                        InternalPayState::RefundSuccess { error, .. } => TransferState::Refunded {
+                            error: format!("{error:?}"),
+                        },
+                        failed => TransferState::Failed {
+                            error: format!("{failed:?}"),
+                        },
+                    };
+                    self.save_transfer(id, &record).await?;
+                    break;
+                }
+            }
+        }
+
+        if !paid {
+            // A payment still awaiting its refund keeps its state, the record shows the funds are on their way back
+            if record.state == TransferState::Pending {
+                record.state = TransferState::Failed {
+                    error: "Lightning update stream ended before the payment completed"
+                        .to_string(),
+                };
+                self.save_transfer(id, &record).await?;
+            }
+            warn!("Transfer {id:?} failed: {:?}", record.state);
+            return Ok((id, record));
+        }
+
+        let mut receive_updates = to_lightning
+            .subscribe_ln_receive(receive_operation_id)
+            .await?
+            .into_stream();
+
+        while let Some(update) = receive_updates.next().await {
+            match update {
+                LnReceiveState::Claimed => {
+                    record.state = TransferState::Succeeded;
+                    break;
+                }
+                LnReceiveState::Canceled { reason } => {
+                    // The source federation paid but the destination federation never claimed the funds
+                    record.state = TransferState::Failed {
+                        error: format!("{reason:?}"),
+                    };
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        if !record.state.is_final() {
+            record.state = TransferState::Failed {
+                error: "Lightning update stream ended before the transfer completed".to_string(),
+            };
+        }
+        self.save_transfer(id, &record).await?;
+
+        info!("Transfer {id:?} finished: {:?}", record.state);
+
+        Ok((id, record))
+    }
+
+    /// Get a transfer by its id.
+    pub async fn transfer_record(&self, id: OperationId) -> Option<TransferRecord> {
+        self.db
+            .begin_transaction_nc()
+            .await
+            .get_value(&TransferKey { id })
+            .await
+    }
+
+    /// List all the transfers made through the multimint.
+    pub async fn transfers(&self) -> Vec<(OperationId, TransferRecord)> {
+        self.db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&TransferKeyPrefix)
+            .await
+            .map(|(key, record)| (key.id, record))
+            .collect::<Vec<_>>()
+            .await
+    }
+
+    async fn save_transfer(&self, id: OperationId, record: &TransferRecord) -> Result<()> {
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_entry(&TransferKey { id }, record).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| anyhow!("Failed to save transfer: {:?}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_utils::{check_cases, federation_id, multimint};
+
+    fn record(state: TransferState) -> TransferRecord {
+        TransferRecord {
+            from_federation_id: federation_id(1),
+            to_federation_id: federation_id(2),
+            amount: Amount::from_sats(1_000),
+            fee: Amount::ZERO,
+            invoice: "lnbc1".to_string(),
+            receive_operation_id: OperationId([1; 32]),
+            pay_operation_id: None,
+            state,
+            created_at: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn transfer_to_the_same_federation_is_rejected() {
+        let multimint = multimint().await;
+        let error = multimint
+            .transfer(&federation_id(1), &federation_id(1), Amount::from_sats(1))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            MultiMintError::find(&error).unwrap().code(),
+            "same_federation"
+        );
+    }
+
+    #[tokio::test]
+    async fn transfer_from_an_unknown_federation_is_rejected() {
+        let multimint = multimint().await;
+        let error = multimint
+            .transfer(&federation_id(1), &federation_id(2), Amount::from_sats(1))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            MultiMintError::find(&error).unwrap().code(),
+            "federation_not_found"
+        );
+        assert!(multimint.transfers().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn saved_transfers_are_listed() {
+        let multimint = multimint().await;
+        let id = OperationId([2; 32]);
+        multimint
+            .save_transfer(id, &record(TransferState::Pending))
+            .await
+            .unwrap();
+        let succeeded = record(TransferState::Succeeded);
+        multimint.save_transfer(id, &succeeded).await.unwrap();
+
+        assert_eq!(multimint.transfer_record(id).await, Some(succeeded.clone()));
+        assert_eq!(multimint.transfers().await, vec![(id, succeeded)]);
+    }
+
+    #[test]
+    fn only_settled_states_are_final() {
+        let error = || "no route".to_string();
+        let cases = [
+            (TransferState::Pending, false),
+            (TransferState::AwaitingRefund { error: error() }, false),
+            (TransferState::Succeeded, true),
+            (TransferState::Refunded { error: error() }, true),
+            (TransferState::Failed { error: error() }, true),
+        ];
+
+        check_cases(cases, |state| state.is_final());
+    }
+
+    #[test]
+    fn state_is_tagged_in_json() {
+        let cases = [
+            (TransferState::Pending, json!({ "state": "pending" })),
+            (
+                TransferState::AwaitingRefund {
+                    error: "no route".to_string(),
+                },
+                json!({ "state": "awaiting_refund", "error": "no route" }),
+            ),
+        ];
+
+        check_cases(cases, |state| serde_json::to_value(state).unwrap());
+    }
+}
@@ -1,6 +1,8 @@
 use std::collections::BTreeMap;
+use std::fmt;
 
-use fedimint_core::{config::FederationId, Amount, TieredSummary};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::{config::FederationId, Amount, PeerId, TieredSummary};
 use serde::Serialize;
 
 /// InfoResponse for getting the Federation Config info
@@ -8,9 +10,143 @@ use serde::Serialize;
 #[serde(rename_all = "snake_case")]
 pub struct InfoResponse {
     pub federation_id: FederationId,
-    pub network: String,
+    /// Bitcoin network of the federation's wallet module, omitted for federations without one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
     pub meta: BTreeMap<String, String>,
     pub total_amount_msat: Amount,
     pub total_num_notes: usize,
     pub denominations_msat: TieredSummary,
 }
+
+/// ReconcileReport of bringing the joined federations in line with a list of invite codes
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ReconcileReport {
+    /// Listed federations that were joined
+    pub joined: Vec<FederationId>,
+    /// Listed federations the multimint already had a client for
+    pub unchanged: Vec<FederationId>,
+    /// Federations that could not be joined or left, with the reason
+    pub failed: BTreeMap<FederationId, String>,
+    /// Unlisted federations that were left, with the balance they held
+    pub left: BTreeMap<FederationId, Amount>,
+    /// Unlisted federations that were kept because they still hold ecash, with their balance
+    pub kept_with_balance: BTreeMap<FederationId, Amount>,
+    /// Unlisted federations that were kept because leaving was not requested
+    pub unlisted: Vec<FederationId>,
+}
+
+impl fmt::Display for ReconcileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} joined, {} unchanged, {} failed, {} left, {} kept with a balance, {} unlisted",
+            self.joined.len(),
+            self.unchanged.len(),
+            self.failed.len(),
+            self.left.len(),
+            self.kept_with_balance.len(),
+            self.unlisted.len()
+        )?;
+        for (federation_id, error) in &self.failed {
+            write!(f, "; {federation_id} failed: {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A guardian of a federation and the endpoint its API is reachable at
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct GuardianInfo {
+    pub name: String,
+    pub url: String,
+}
+
+/// PreviewResponse for a federation that has not been joined yet
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct PreviewResponse {
+    pub federation_id: FederationId,
+    pub federation_name: Option<String>,
+    pub guardians: BTreeMap<PeerId, GuardianInfo>,
+    /// Bitcoin network of the federation's wallet module, if it has one
+    pub network: Option<String>,
+    /// Module kinds keyed by module instance id
+    pub modules: BTreeMap<ModuleInstanceId, String>,
+    pub meta: BTreeMap<String, String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::federation_id;
+
+    fn info_response(network: Option<String>) -> InfoResponse {
+        InfoResponse {
+            federation_id: federation_id(0),
+            network,
+            meta: BTreeMap::new(),
+            total_amount_msat: Amount::ZERO,
+            total_num_notes: 0,
+            denominations_msat: TieredSummary::default(),
+        }
+    }
+
+    #[test]
+    fn info_includes_network_of_wallet() {
+        let info = serde_json::to_value(info_response(Some("signet".to_string()))).unwrap();
+        assert_eq!(info["network"], "signet");
+    }
+
+    #[test]
+    fn info_omits_network_without_wallet() {
+        let info = serde_json::to_value(info_response(None)).unwrap();
+        assert!(info.get("network").is_none());
+        assert_eq!(info["total_num_notes"], 0);
+    }
+
+    #[test]
+    fn preview_keys_guardians_and_modules_by_id() {
+        let preview = PreviewResponse {
+            federation_id: federation_id(1),
+            federation_name: Some("Fed".to_string()),
+            guardians: BTreeMap::from([(
+                PeerId::from(0),
+                GuardianInfo {
+                    name: "alice".to_string(),
+                    url: "wss://alice.example/".to_string(),
+                },
+            )]),
+            network: None,
+            modules: BTreeMap::from([(0, "ln".to_string()), (1, "mint".to_string())]),
+            meta: BTreeMap::new(),
+        };
+
+        let preview = serde_json::to_value(preview).unwrap();
+        assert_eq!(preview["federation_name"], "Fed");
+        assert_eq!(preview["guardians"]["0"]["name"], "alice");
+        assert_eq!(preview["guardians"]["0"]["url"], "wss://alice.example/");
+        assert_eq!(preview["modules"]["1"], "mint");
+        assert!(preview["network"].is_null());
+    }
+
+    #[test]
+    fn reconcile_report_lists_failures() {
+        let joined = federation_id(1);
+        let failed = federation_id(2);
+        let report = ReconcileReport {
+            joined: vec![joined],
+            failed: BTreeMap::from([(failed, "guardians unreachable".to_string())]),
+            ..ReconcileReport::default()
+        };
+
+        assert_eq!(
+            report.to_string(),
+            format!(
+                "1 joined, 0 unchanged, 1 failed, 0 left, 0 kept with a balance, 0 unlisted; {failed} failed: guardians unreachable"
+            )
+        );
+    }
+}